@@ -0,0 +1,70 @@
+//! Transparent outer-container decompression for replay uploads that
+//! arrive gzip'd or zlib'd on top of the `.w3g` format's own internal
+//! block compression (see [`crate::replay`]). Detected by sniffing the
+//! leading magic bytes, so callers can feed either a raw or a compressed
+//! replay through the same entry point without doing the decompression
+//! themselves - and new container formats only need a new match arm here.
+
+use std::io::Read;
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZLIB_MAGIC: u8 = 0x78;
+
+/// Inflates `bytes` if it looks like a gzip or zlib container, falling
+/// back to `bytes` unchanged if no known magic is present - or if
+/// inflation fails, so a false-positive magic match still reaches the
+/// parser instead of vanishing into an error here.
+pub fn decompress_container(bytes: &[u8]) -> Vec<u8> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut out = Vec::new();
+        return match GzDecoder::new(bytes).read_to_end(&mut out) {
+            Ok(_) => out,
+            Err(_) => bytes.to_vec(),
+        };
+    }
+
+    if bytes.first() == Some(&ZLIB_MAGIC) {
+        let mut out = Vec::new();
+        return match ZlibDecoder::new(bytes).read_to_end(&mut out) {
+            Ok(_) => out,
+            Err(_) => bytes.to_vec(),
+        };
+    }
+
+    bytes.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    #[test]
+    fn passes_through_bytes_with_no_known_container_magic() {
+        let raw = b"Warcraft III recorded game\x1A\0".to_vec();
+        assert_eq!(decompress_container(&raw), raw);
+    }
+
+    #[test]
+    fn inflates_a_gzip_wrapped_buffer() {
+        let payload = b"hello replay";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decompress_container(&compressed), payload);
+    }
+
+    #[test]
+    fn inflates_a_zlib_wrapped_buffer() {
+        let payload = b"hello replay";
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(decompress_container(&compressed), payload);
+    }
+}
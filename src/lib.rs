@@ -1,24 +1,167 @@
 mod utils;
+mod sanitize;
+mod container;
 mod replay;
 
+use js_sys::{Float64Array, Object, Reflect, Uint8Array};
 use serde::Serialize;
 use wasm_bindgen::prelude::*;
-use crate::replay::Replay;
+use crate::container::decompress_container;
+use crate::replay::{ParseOptions, ParseSections, Replay};
+use crate::sanitize::SanitizeOptions;
 
-// When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
-// allocator.
+// When the `wee_alloc` feature is enabled, use `lol_alloc` as the global
+// allocator. The feature keeps its old name so downstream build configs
+// (wasm-pack profiles, CI flags) don't need to change, even though the
+// allocator behind it moved off the unmaintained `wee_alloc` crate
+// (GHSA-rc23-xxgq-x27g, and nightly-only on wasm) onto `lol_alloc`, which
+// gets us the same small-footprint win on stable Rust. This crate is only
+// ever touched from the single JS-owned WASM thread, so the
+// `AssumeSingleThreaded` wrapper `lol_alloc`'s free-list allocator needs
+// to implement `GlobalAlloc` is sound here.
+//
+// BLOCKED/INCOMPLETE: the request asked for a parse-throughput and binary-
+// size comparison (default allocator vs. this one). That hasn't been
+// produced - this source tree has no `Cargo.toml`, so there's no manifest
+// to add a `criterion` dev-dependency or a `[[bench]]` target to, and the
+// crate's only public entry points take a full `.w3g` buffer (raw or
+// gzip/zlib-wrapped), which needs a real recorded-game fixture (not checked in here)
+// to benchmark meaningfully. Run both comparisons once the manifest and a
+// sample replay exist: `cargo bench --features wee_alloc` vs. without, and
+// `wasm-pack build --release [--features wee_alloc] && ls -la pkg/*_bg.wasm`.
 #[cfg(feature = "wee_alloc")]
 #[global_allocator]
-static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
+static ALLOC: lol_alloc::AssumeSingleThreaded<lol_alloc::FreeListAllocator> =
+    unsafe { lol_alloc::AssumeSingleThreaded::new(lol_alloc::FreeListAllocator::new()) };
 
+/// JSON envelope returned by [`parse_replay_file`], so a parse failure is
+/// a value the host can inspect (`ok: false, error: Some(...)`) rather
+/// than a trap or a thrown exception.
+#[derive(Serialize)]
+struct ParseResult {
+    ok: bool,
+    error: Option<String>,
+    replay: Option<Replay>,
+}
+
+/// Accepts a raw `.w3g` buffer, or one wrapped in a gzip/zlib container
+/// (sniffed and transparently inflated by [`decompress_container`]), and
+/// parses it fully. Always returns valid JSON - on failure, `ok` is
+/// `false` and `error` describes what went wrong - instead of throwing,
+/// so malformed input surfaces as a message the browser can show rather
+/// than an opaque WASM trap.
 #[wasm_bindgen]
 pub fn parse_replay_file(bytes: &[u8]) -> String {
-    let replay = Replay::from_bytes(&bytes);
-    return serde_json::to_string(&replay).unwrap();
+    let bytes = decompress_container(&bytes);
+    let result = match Replay::from_bytes(&bytes) {
+        Ok(replay) => ParseResult { ok: true, error: None, replay: Some(replay) },
+        Err(err) => ParseResult { ok: false, error: Some(err.to_string()), replay: None },
+    };
+    serde_json::to_string(&result)
+        .unwrap_or_else(|_| "{\"ok\":false,\"error\":\"failed to serialize replay\",\"replay\":null}".to_string())
+}
+
+/// Same envelope as [`parse_replay_file`], but built directly as a
+/// `JsValue` object graph via `serde_wasm_bindgen` instead of a JSON
+/// string - skips the UTF-8 string + `JSON.parse` round trip, so this is
+/// the one to reach for on large replays with thousands of frames.
+/// `parse_replay_file` is kept around for callers that want a plain
+/// string (e.g. to store or hash).
+#[wasm_bindgen]
+pub fn parse_replay_file_js(bytes: &[u8]) -> Result<JsValue, JsValue> {
+    let bytes = decompress_container(&bytes);
+    let result = match Replay::from_bytes(&bytes) {
+        Ok(replay) => ParseResult { ok: true, error: None, replay: Some(replay) },
+        Err(err) => ParseResult { ok: false, error: Some(err.to_string()), replay: None },
+    };
+    serde_wasm_bindgen::to_value(&result).map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+/// Same replay as [`parse_replay_file`], but skips the `ReplayData`
+/// section (chat, actions, leave events) entirely - for list views that
+/// only need map/player/duration metadata and don't want to pay for
+/// walking the whole action stream of every replay.
+#[wasm_bindgen]
+pub fn parse_replay_header(bytes: &[u8]) -> Result<String, JsValue> {
+    let bytes = decompress_container(&bytes);
+    let replay = Replay::from_header_bytes(&bytes).map_err(|err| JsValue::from_str(&err.to_string()))?;
+    Ok(serde_json::to_string(&replay).unwrap())
+}
+
+/// Same replay as [`parse_replay_file`], but lets the caller pick which
+/// sections to decode instead of being stuck with the `parse_replay_file`
+/// (everything) / [`parse_replay_header`] (header only) presets -
+/// `include_body` selects between the two. The header, game settings,
+/// slots and player list are always decoded either way; `include_body`
+/// only gates the expensive per-frame `ReplayData` walk.
+///
+/// `parse_colors` is the only way to reach
+/// [`SanitizeOptions::parse_colors`] from JS - `parse_replay_file` and
+/// friends always sanitize chat/names down to plain text, so a UI that
+/// wants the `|c…|r` color spans back as structured segments needs this
+/// entry point.
+#[wasm_bindgen]
+pub fn parse_replay_file_with(bytes: &[u8], include_body: bool, parse_colors: bool) -> Result<JsValue, JsValue> {
+    let bytes = decompress_container(&bytes);
+    let sections = if include_body { ParseSections::full() } else { ParseSections::header() };
+    let sanitize_options = SanitizeOptions { parse_colors };
+    let replay = Replay::from_bytes_with_options(&bytes, &sanitize_options, &ParseOptions { recover: true }, &sections)
+        .map_err(|err| JsValue::from_str(&err.to_string()))?;
+    serde_wasm_bindgen::to_value(&replay).map_err(|err| JsValue::from_str(&err.to_string()))
+}
+
+/// Same replay as [`parse_replay_file`], but returns `actions` as an
+/// object of parallel typed arrays (`playerId`, `timestamp`, `actionType`,
+/// `targetX`, `targetY`) instead of one JS object per action, for
+/// analytics code that wants to scan millions of actions cheaply.
+#[wasm_bindgen]
+pub fn parse_replay_actions_columnar(bytes: &[u8]) -> Result<JsValue, JsValue> {
+    let bytes = decompress_container(&bytes);
+    let replay = Replay::from_bytes(&bytes).map_err(|err| JsValue::from_str(&err.to_string()))?;
+    let columns = replay.actions_columnar();
+
+    let timestamps: Vec<f64> = columns.timestamp.iter().map(|t| *t as f64).collect();
+    let target_x: Vec<f64> = columns.target_x.iter().map(|v| *v as f64).collect();
+    let target_y: Vec<f64> = columns.target_y.iter().map(|v| *v as f64).collect();
+
+    let result = Object::new();
+    Reflect::set(&result, &"playerId".into(), &Uint8Array::from(columns.player_id.as_slice()))?;
+    Reflect::set(&result, &"timestamp".into(), &Float64Array::from(timestamps.as_slice()))?;
+    Reflect::set(&result, &"actionType".into(), &Uint8Array::from(columns.action_type.as_slice()))?;
+    Reflect::set(&result, &"targetX".into(), &Float64Array::from(target_x.as_slice()))?;
+    Reflect::set(&result, &"targetY".into(), &Float64Array::from(target_y.as_slice()))?;
+
+    Ok(result.into())
 }
 
 #[wasm_bindgen]
 pub fn debug_init() {
     utils::set_panic_hook();
     console_log::init().unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `parse_replay_file_js` builds this same `ParseResult` and hands it to
+    // `serde_wasm_bindgen::to_value` instead of `serde_json::to_string` -
+    // the `JsValue` path only pays off if the two keep producing the same
+    // shape, so pin that shape here where it can run without a wasm target.
+    #[test]
+    fn parse_result_failure_serializes_ok_false_with_no_replay() {
+        let result = ParseResult { ok: false, error: Some("bad header magic".to_string()), replay: None };
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["ok"], false);
+        assert_eq!(json["error"], "bad header magic");
+        assert!(json["replay"].is_null());
+    }
+
+    #[test]
+    fn parse_result_success_serializes_ok_true_with_no_error() {
+        let result = ParseResult { ok: true, error: None, replay: None };
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["ok"], true);
+        assert!(json["error"].is_null());
+    }
 }
\ No newline at end of file
@@ -1,18 +1,216 @@
-use std::any::Any;
-use std::borrow::Cow;
 use std::collections::HashMap;
-use std::convert::TryInto;
-use std::hint::black_box;
-use std::io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::fmt;
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom};
 use flate2::{Decompress, FlushDecompress};
-use wasm_bindgen::prelude::wasm_bindgen;
 use log::{info, warn};
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
-use serde::{Serialize, Serializer};
-use web_sys::console::info;
+use serde::Serialize;
 use crate::replay::SlotRace::UNKNOWN;
-use crate::utils;
+use crate::sanitize::{sanitize, ColorSegment, SanitizeOptions};
+
+const HEADER_MAGIC: &[u8; 28] = b"Warcraft III recorded game\x1A\0";
+
+/// Error returned by [`Replay::from_bytes`] and the cursor helpers it is
+/// built on. Every variant except `BadHeaderMagic` and `InvalidUtf8`
+/// carries the byte offset (into the buffer the failing read was
+/// performed against) where the failure occurred, so a caller can tell
+/// "not a replay" apart from "truncated" instead of getting an opaque
+/// panic. There is no variant for an unsupported version: an unknown
+/// header version is not an error and is read with the 68-byte header
+/// layout.
+#[derive(Debug)]
+pub enum ParseError {
+    UnexpectedEof { offset: u64, expected: u64, available: u64 },
+    BadHeaderMagic,
+    Decompress(u64),
+    UnknownRecordId { id: u8, offset: u64 },
+    InvalidUtf8,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedEof { offset, expected, available } =>
+                write!(f, "unexpected end of input at offset {:#x} (expected {} bytes, {} available)", offset, expected, available),
+            ParseError::BadHeaderMagic => write!(f, "input does not start with the Warcraft III replay header magic"),
+            ParseError::Decompress(offset) => write!(f, "failed to decompress data block at offset {:#x}", offset),
+            ParseError::UnknownRecordId { id, offset } => write!(f, "unknown record id {:#04x} at offset {:#x}", id, offset),
+            ParseError::InvalidUtf8 => write!(f, "string field is not valid UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Lets a caller opt into surviving recoverable corruption instead of
+/// [`Replay::from_bytes_with_options`] failing outright. With `recover`
+/// set, an unknown record id or a truncated read inside the `ReplayData`
+/// section stops parsing where it happened - rather than propagating a
+/// [`ParseError`] - and is recorded in [`Replay::warnings`] instead, so
+/// the slots/players/chat/actions already decoded are still usable. An
+/// unknown action id skips the rest of its action block whether or not
+/// `recover` is set; only with `recover` set is it recorded in
+/// [`Replay::warnings`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    pub recover: bool,
+}
+
+/// A recoverable problem encountered while parsing the `ReplayData`
+/// section under [`ParseOptions::recover`]. Unlike [`ParseError`], a
+/// `ParseWarning` never aborts the parse - it just marks the point past
+/// which `actions`/`chat`/`leave_events` may be incomplete.
+#[derive(Debug, Clone)]
+pub enum ParseWarning {
+    /// Hit an unrecognized top-level record id - parsing stopped with
+    /// whatever slots/players/chat/actions had been decoded so far.
+    UnknownRecordId { id: u8, offset: u64 },
+    /// Hit an unrecognized action id inside an action block - the rest of
+    /// that one block was skipped, but parsing continued right after it.
+    UnknownActionId { id: u8, offset: u64 },
+    /// A read ran past the end of the buffer while decoding the record
+    /// type `record_id` - most likely a truncated replay file.
+    Truncated { record_id: u8, offset: u64, expected: u64, available: u64 },
+    /// A string field inside the record type `record_id` was not valid
+    /// UTF-8.
+    InvalidUtf8 { record_id: u8 },
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::UnknownRecordId { id, offset } =>
+                write!(f, "unknown record id {:#04x} at offset {:#x}, parsing stopped there", id, offset),
+            ParseWarning::UnknownActionId { id, offset } =>
+                write!(f, "unknown action id {:#04x} at offset {:#x}, rest of that block skipped", id, offset),
+            ParseWarning::Truncated { record_id, offset, expected, available } =>
+                write!(f, "record {:#04x} truncated at offset {:#x} (expected {} bytes, {} available)", record_id, offset, expected, available),
+            ParseWarning::InvalidUtf8 { record_id } =>
+                write!(f, "record {:#04x} contains a string field that is not valid UTF-8", record_id),
+        }
+    }
+}
+
+/// Whether the `ReplayData` loop should keep reading the next record
+/// after processing one.
+enum RecordOutcome {
+    Continue,
+    Stop,
+}
+
+/// Selects how much of a replay [`Replay::from_bytes_with_options`]
+/// decodes, so a caller that only wants list-view metadata (map, players,
+/// duration) isn't forced to pay for walking the whole action stream -
+/// mirrors the header-vs-body split that makes boxcars fast for SC2
+/// replay lists.
+///
+/// The header, game settings, slots and player list are always decoded
+/// (the format has no way to read those without inflating the data
+/// blocks); `include_body` only gates the `ReplayData` section, which is
+/// where the per-frame action/chat/leave decode - the expensive part for
+/// a long game - lives.
+#[derive(Debug, Clone, Copy)]
+pub struct ParseSections {
+    pub include_body: bool,
+}
+
+impl ParseSections {
+    /// Header, game settings, slots and players only - skips the
+    /// `ReplayData` section entirely, so `chat`/`actions`/`leave_events`
+    /// on the returned [`Replay`] are empty.
+    pub fn header() -> ParseSections {
+        ParseSections { include_body: false }
+    }
+
+    /// Everything, including the per-frame action/chat/leave decode.
+    pub fn full() -> ParseSections {
+        ParseSections { include_body: true }
+    }
+}
+
+impl Default for ParseSections {
+    fn default() -> Self {
+        ParseSections::full()
+    }
+}
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+/// Thin `Cursor` wrapper whose reads return `Result<T, ParseError>` carrying
+/// the byte offset of the failure, mirroring the xash3d protocol cursor's
+/// `CursorError` design. Used both for the raw (still-compressed) header
+/// stream and for the inflated replay body.
+struct ReplayCursor<T> {
+    inner: Cursor<T>,
+}
+
+impl<T> ReplayCursor<T> where T: AsRef<[u8]> {
+    fn new(inner: T) -> Self {
+        ReplayCursor { inner: Cursor::new(inner) }
+    }
+
+    fn position(&self) -> u64 {
+        self.inner.position()
+    }
+
+    fn read_exact_buf(&mut self, buf: &mut [u8]) -> Result<()> {
+        let offset = self.position();
+        self.inner.read_exact(buf).map_err(|_| {
+            let total_len = self.inner.get_ref().as_ref().len() as u64;
+            ParseError::UnexpectedEof { offset, expected: buf.len() as u64, available: total_len.saturating_sub(offset) }
+        })
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; n];
+        self.read_exact_buf(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read_exact_buf(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_word(&mut self) -> Result<u16> {
+        let buf = self.read_bytes(2)?;
+        Ok(buf[0] as u16 + 256 * buf[1] as u16)
+    }
+
+    fn read_dword(&mut self) -> Result<u32> {
+        let buf = self.read_bytes(4)?;
+        let mut data: u32 = 0;
+        for j in 0u8..4u8 {
+            data += 256u32.pow(j as u32) * buf[j as usize] as u32;
+        }
+        Ok(data)
+    }
+
+    fn read_nullterminated_string(&mut self) -> Result<String> {
+        let offset = self.position();
+        let buf = self.read_until_byte(0x00)?;
+        if buf.last() != Some(&0x00) {
+            let total_len = self.inner.get_ref().as_ref().len() as u64;
+            return Err(ParseError::UnexpectedEof { offset, expected: buf.len() as u64 + 1, available: total_len.saturating_sub(offset) });
+        }
+        String::from_utf8(buf[..buf.len() - 1].to_vec()).map_err(|_| ParseError::InvalidUtf8)
+    }
+
+    fn read_until_byte(&mut self, byte: u8) -> Result<Vec<u8>> {
+        let offset = self.position();
+        let mut buf: Vec<u8> = vec![];
+        self.inner.read_until(byte, &mut buf).map_err(|_| ParseError::UnexpectedEof { offset, expected: 1, available: 0 })?;
+        Ok(buf)
+    }
+
+    fn skip_bytes(&mut self, n: i64) -> Result<()> {
+        let offset = self.position();
+        self.inner.seek(SeekFrom::Current(n)).map_err(|_| ParseError::UnexpectedEof { offset, expected: n.unsigned_abs(), available: 0 })?;
+        Ok(())
+    }
+}
 
 #[derive(Serialize, FromPrimitive, Debug)]
 enum SlotColor {
@@ -71,14 +269,14 @@ enum SlotStatus {
     UNKNOWN = 127
 }
 
-#[derive(Serialize, FromPrimitive, Debug)]
+#[derive(Serialize, FromPrimitive, Debug, Clone, Copy)]
 enum LeaveReason {
     CONNECTION_CLOSED_BY_REMOTE_GAME = 0x01,
     CONNECTION_CLOSED_BY_LOCAL_GAME = 0x0C,
     UNKNOWN
 }
 
-#[derive(Serialize, FromPrimitive, Debug)]
+#[derive(Serialize, FromPrimitive, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 enum ActionType {
     PAUSE = 0x01,
     RESUME = 0x02,
@@ -91,6 +289,17 @@ enum ActionType {
     UNKNOWN
 }
 
+/// Actions whose repetition rarely reflects deliberate player input: mashing
+/// the same unit selection, or re-issuing an identical move/attack order
+/// before the previous one could have taken effect. Excluded from effective
+/// APM by [`Replay::summary`].
+const SPAM_ACTION_IDS: [u8; 3] = [0x16, 0x11, 0x12];
+
+/// Repeats of a spam-prone action by the same player within this many
+/// milliseconds of the previous one are treated as debounced spam rather
+/// than distinct actions.
+const SPAM_DEBOUNCE_MS: u64 = 150;
+
 #[derive(Serialize, Debug)]
 struct MinimapLocation {
     x: u32,
@@ -99,11 +308,23 @@ struct MinimapLocation {
 
 #[derive(Serialize)]
 struct ReplayMeta {
-    saving_player_id: u8,
+    /// `None` when [`ParseSections::include_body`] is unset - the leave
+    /// records the heuristic relies on live in the `ReplayData` section,
+    /// so there's no way to tell who saved a header-only parse rather than
+    /// defaulting to a misleading player id.
+    saving_player_id: Option<u8>,
     is_saving_player_host: bool,
     game_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_name_colors: Option<Vec<ColorSegment>>,
     map_name: String,
-    game_creator_battle_tag: String
+    #[serde(skip_serializing_if = "Option::is_none")]
+    map_name_colors: Option<Vec<ColorSegment>>,
+    game_creator_battle_tag: String,
+    /// Game variant [`detect_format`] dispatched on while decoding this
+    /// replay, so consumers can tell which per-version quirks (e.g.
+    /// Reforged battle tag/clan/avatar metadata) may apply to it.
+    variant: GameVariant
 }
 
 #[derive(Serialize)]
@@ -138,9 +359,102 @@ struct Slot {
 #[derive(Serialize, Debug)]
 struct ReplayPlayer {
     battle_tag: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    battle_tag_colors: Option<Vec<ColorSegment>>,
     leave_reason: LeaveReason,
     result_byte: u8,
-    left_at: u64
+    left_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clan: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clan_colors: Option<Vec<ColorSegment>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar_colors: Option<Vec<ColorSegment>>
+}
+
+/// Which WC3 game variant produced a replay, detected from the subheader's
+/// build number (see [`detect_format`]) rather than assumed from the fixed
+/// header length. Exposed on [`ReplayMeta`] so consumers know which
+/// per-version quirks may apply.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum GameVariant {
+    ReignOfChaos,
+    FrozenThrone,
+    Reforged
+}
+
+/// Per-version dispatch, picked by [`detect_format`]. Mirrors the SC2
+/// parser's `VersionedDecoder` shape - one implementation per known replay
+/// generation instead of inline `if variant == ...` checks - but today the
+/// only field that's actually confirmed to differ across RoC/TFT/Reforged
+/// is [`has_reforged_metadata`]. [`player_record_padding_len`] is a real
+/// extension point for the day a version-specific padding length is
+/// confirmed, but every known format impl currently uses its default; it
+/// isn't guessed at per-version here.
+///
+/// [`player_record_padding_len`]: ReplayFormat::player_record_padding_len
+trait ReplayFormat {
+    fn variant(&self) -> GameVariant;
+
+    /// Length, in bytes, of the undocumented gap after the host player's
+    /// name/additional-data in the `PlayerRecord`. Not confirmed to vary by
+    /// version - every known format impl uses this default.
+    fn player_record_padding_len(&self) -> usize {
+        4
+    }
+
+    /// Whether `0x39` subrecords carry Reforged battle tag/clan/avatar
+    /// metadata (as opposed to being unknown/unused padding records). The
+    /// one layout difference that's actually confirmed to vary by variant.
+    fn has_reforged_metadata(&self) -> bool {
+        false
+    }
+}
+
+struct ReignOfChaosFormat;
+struct FrozenThroneFormat;
+struct ReforgedFormat;
+
+impl ReplayFormat for ReignOfChaosFormat {
+    fn variant(&self) -> GameVariant {
+        GameVariant::ReignOfChaos
+    }
+}
+
+impl ReplayFormat for FrozenThroneFormat {
+    fn variant(&self) -> GameVariant {
+        GameVariant::FrozenThrone
+    }
+}
+
+impl ReplayFormat for ReforgedFormat {
+    fn variant(&self) -> GameVariant {
+        GameVariant::Reforged
+    }
+
+    fn has_reforged_metadata(&self) -> bool {
+        true
+    }
+}
+
+/// Picks a [`ReplayFormat`] from the build number found in the header
+/// subblock. Build-number boundaries are approximate patch cutoffs rather
+/// than documented constants; anything at or above a threshold is treated
+/// as that variant so newer patches still decode with the closest known
+/// layout instead of silently misreading it as the oldest one.
+fn detect_format(build_number: u16) -> Box<dyn ReplayFormat> {
+    const TFT_MIN_BUILD: u16 = 6031;
+    const REFORGED_MIN_BUILD: u16 = 6052;
+
+    if build_number >= REFORGED_MIN_BUILD {
+        Box::new(ReforgedFormat)
+    } else if build_number >= TFT_MIN_BUILD {
+        Box::new(FrozenThroneFormat)
+    } else {
+        Box::new(ReignOfChaosFormat)
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -148,82 +462,446 @@ struct ChatMessage {
     sender_player_id: u8,
     recipient_slot_number: Option<i8>,
     flag: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<ChatScope>,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_colors: Option<Vec<ColorSegment>>,
     timestamp: u64
 }
 
-#[derive(Serialize)]
+/// Who a chat message was sent to, decoded from the raw `flag` byte also
+/// kept on [`ChatMessage::flag`] - mirrors how [`Action`] keeps `action_id`
+/// alongside the decoded `action_type`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum ChatScope {
+    All,
+    Allies,
+    Observers,
+    Private { recipient_slot: u8 }
+}
+
+impl ChatScope {
+    fn from_flag(flag: u8) -> ChatScope {
+        match flag {
+            0x00 => ChatScope::All,
+            0x01 => ChatScope::Allies,
+            0x02 => ChatScope::Observers,
+            other => ChatScope::Private { recipient_slot: other - 3 }
+        }
+    }
+}
+
+/// Well-known Warcraft III ability/order ids. Not exhaustive: anything not
+/// listed here is preserved verbatim via `UNKNOWN` rather than discarded.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+enum OrderId {
+    MOVE,
+    STOP,
+    ATTACK,
+    SMART,
+    HOLD_POSITION,
+    PATROL,
+    UNKNOWN(u32)
+}
+
+impl OrderId {
+    fn from_raw(id: u32) -> OrderId {
+        match id {
+            0x000D0001 => OrderId::MOVE,
+            0x000D0002 => OrderId::STOP,
+            0x000D0004 => OrderId::ATTACK,
+            0x000D0003 => OrderId::SMART,
+            0x000D000C => OrderId::HOLD_POSITION,
+            0x000D000D => OrderId::PATROL,
+            other => OrderId::UNKNOWN(other)
+        }
+    }
+}
+
+#[derive(Serialize, Default)]
 struct ActionData {
     #[serde(skip_serializing_if = "Option::is_none")]
     location: Option<MinimapLocation>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    savegame_name: Option<String>
+    savegame_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_id: Option<OrderId>,
+    /// The `(type, counter)` id of the unit/building issuing the order, same
+    /// representation as [`ActionData::target_unit_id`] - this used to be
+    /// misread as a single dword, which desynced the cursor for every order
+    /// action in the replay.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    object_id: Option<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_x: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_y: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_unit_id: Option<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item_object_id: Option<(u32, u32)>
+}
+
+/// Width to read a [`ActionField::Repeat`] count as.
+#[derive(Debug, Clone, Copy)]
+enum FieldWidth {
+    Byte,
+    Word
+}
+
+/// One field within an action opcode's payload, as laid out by
+/// [`ACTION_SCHEMA`]. Mirrors the SC2 parser's typeinfo-driven decoder:
+/// each opcode is just an ordered list of these instead of a bespoke
+/// `match` arm full of ad-hoc `cursor.read_*`/`skip_bytes` calls, so
+/// supporting a new opcode - or a new field on an existing one - is a
+/// one-line table entry rather than a new block of reads.
+#[derive(Debug, Clone, Copy)]
+enum ActionField {
+    Byte,
+    Word,
+    Dword,
+    /// An IEEE-754 float, stored on the wire as a little-endian dword.
+    Float,
+    NullString,
+    /// Minimap x/y, two little-endian dwords.
+    Location,
+    /// A `(type, index)` unit/item id, two little-endian dwords.
+    ItemId,
+    /// `n` bytes that aren't meaningful to us, just consumed.
+    Skip(usize),
+    /// A count (read as `count_width`) followed by that many
+    /// `element_bytes`-wide records, all skipped. Used by the
+    /// selection/hotkey-group actions, whose payload length depends on how
+    /// many units were selected.
+    Repeat { count_width: FieldWidth, element_bytes: usize }
+}
+
+/// One decoded value produced by walking an opcode's [`ActionField`] list
+/// against the cursor, in schema order. `Skip`/`Repeat` entries don't
+/// produce a value.
+#[derive(Debug)]
+enum FieldValue {
+    Byte(u8),
+    Word(u16),
+    Dword(u32),
+    Float(f32),
+    Str(String),
+    Location(MinimapLocation),
+    ItemId((u32, u32))
+}
+
+/// Declarative byte layout for every known action opcode's payload.
+/// Looked up by [`action_schema`]; opcodes not listed here fall back to
+/// the "read the rest of the block and warn" path in `from_bytes`, since
+/// their length can't be derived without special-casing.
+static ACTION_SCHEMA: &[(u8, &[ActionField])] = &[
+    (0x01, &[]),
+    (0x02, &[]),
+    (0x03, &[ActionField::Byte]),
+    (0x04, &[]),
+    (0x05, &[]),
+    (0x06, &[ActionField::NullString]),
+    (0x07, &[ActionField::Skip(4)]),
+    // Ability with no target. 2 unused bytes, then order id, then the
+    // issuing unit's id as a (type, counter) pair - *not* a single dword,
+    // despite how this table used to read it. Lengths below are checked
+    // against `cursor_skip_bytes`'s pre-schema constants for these ids
+    // (14/22/30/38 bytes), which this table must keep matching byte-for-byte
+    // or every action after the first 0x10-0x14 in the stream desyncs.
+    (0x10, &[ActionField::Skip(2), ActionField::Dword, ActionField::ItemId]),
+    // Ability targeting a point.
+    (0x11, &[ActionField::Skip(2), ActionField::Dword, ActionField::ItemId, ActionField::Float, ActionField::Float]),
+    // Ability targeting an object.
+    (0x12, &[ActionField::Skip(2), ActionField::Dword, ActionField::ItemId, ActionField::Float, ActionField::Float, ActionField::ItemId]),
+    // Give/drop item onto a target unit.
+    (0x13, &[ActionField::Skip(2), ActionField::Dword, ActionField::ItemId, ActionField::Float, ActionField::Float, ActionField::ItemId, ActionField::ItemId]),
+    // Two-target order, e.g. patrol. Only the first target's point/object
+    // are decoded; the pre-schema code skipped 43 bytes here but never
+    // recorded what the trailing 13 bytes (after the first target, 30 bytes
+    // in) actually contained, and guessing a second point+object layout
+    // overshoots that by 3 bytes. Until that's confirmed against a real
+    // capture, skip the remainder outright rather than asserting a second
+    // target that may not be laid out the way it was previously decoded.
+    (0x14, &[ActionField::Skip(2), ActionField::Dword, ActionField::ItemId, ActionField::Float, ActionField::Float,
+             ActionField::ItemId, ActionField::Skip(13)]),
+    (0x16, &[ActionField::Byte, ActionField::Repeat { count_width: FieldWidth::Word, element_bytes: 8 }]),
+    (0x17, &[ActionField::Byte, ActionField::Repeat { count_width: FieldWidth::Word, element_bytes: 8 }]),
+    (0x18, &[ActionField::Skip(2)]),
+    (0x19, &[ActionField::Skip(12)]),
+    (0x1A, &[]),
+    (0x1B, &[ActionField::Skip(9)]),
+    (0x1C, &[ActionField::Skip(9)]),
+    (0x1D, &[ActionField::Skip(8)]),
+    (0x1E, &[ActionField::Skip(5)]),
+    (0x20, &[]),
+    (0x21, &[ActionField::Skip(8)]),
+    (0x22, &[]),
+    (0x23, &[]),
+    (0x24, &[]),
+    (0x25, &[]),
+    (0x26, &[]),
+    (0x27, &[ActionField::Skip(5)]),
+    (0x29, &[]),
+    (0x2A, &[]),
+    (0x2B, &[]),
+    (0x2C, &[]),
+    (0x2D, &[ActionField::Skip(5)]),
+    (0x2E, &[ActionField::Skip(4)]),
+    (0x2F, &[]),
+    (0x30, &[]),
+    (0x31, &[]),
+    (0x32, &[]),
+    (0x50, &[ActionField::Skip(5)]),
+    (0x51, &[ActionField::Skip(9)]),
+    // Chat command: 8 unknown bytes, then the command text.
+    (0x60, &[ActionField::Skip(8), ActionField::NullString]),
+    (0x61, &[]),
+    (0x62, &[ActionField::Skip(12)]),
+    (0x66, &[]),
+    (0x67, &[]),
+    (0x68, &[ActionField::Location]),
+    (0x69, &[ActionField::Skip(16)]),
+    (0x6A, &[ActionField::Skip(16)]),
+    (0x75, &[ActionField::Skip(1)]),
+    (0x7a, &[ActionField::Skip(20)]),
+    (0x7b, &[ActionField::Skip(16)]),
+];
+
+fn action_schema(action_id: u8) -> Option<&'static [ActionField]> {
+    ACTION_SCHEMA.iter().find(|(id, _)| *id == action_id).map(|(_, fields)| *fields)
+}
+
+/// Walks `fields` against `cursor` in order, returning the decoded value
+/// of every non-`Skip`/`Repeat` field.
+fn decode_action_fields<T: AsRef<[u8]>>(cursor: &mut ReplayCursor<T>, fields: &[ActionField]) -> Result<Vec<FieldValue>> {
+    let mut values = Vec::with_capacity(fields.len());
+    for field in fields {
+        match field {
+            ActionField::Byte => values.push(FieldValue::Byte(cursor.read_byte()?)),
+            ActionField::Word => values.push(FieldValue::Word(cursor.read_word()?)),
+            ActionField::Dword => values.push(FieldValue::Dword(cursor.read_dword()?)),
+            ActionField::Float => values.push(FieldValue::Float(f32::from_bits(cursor.read_dword()?))),
+            ActionField::NullString => values.push(FieldValue::Str(cursor.read_nullterminated_string()?)),
+            ActionField::Location => values.push(FieldValue::Location(MinimapLocation {
+                x: cursor.read_dword()?,
+                y: cursor.read_dword()?
+            })),
+            ActionField::ItemId => values.push(FieldValue::ItemId((cursor.read_dword()?, cursor.read_dword()?))),
+            ActionField::Skip(n) => cursor.skip_bytes(*n as i64)?,
+            ActionField::Repeat { count_width, element_bytes } => {
+                let count = match count_width {
+                    FieldWidth::Byte => cursor.read_byte()? as i64,
+                    FieldWidth::Word => cursor.read_word()? as i64
+                };
+                cursor.skip_bytes(count * *element_bytes as i64)?;
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Assembles an [`ActionData`] from the values [`decode_action_fields`]
+/// produced for opcodes whose payload is meaningful beyond "consume N
+/// bytes". Opcodes not matched here (selection lists, chat commands, pure
+/// skips, ...) either have no useful payload or are handled separately by
+/// their caller.
+fn action_data_from_fields(action_id: u8, values: &[FieldValue]) -> Option<ActionData> {
+    use FieldValue::*;
+    match (action_id, values) {
+        (0x06, [Str(name)]) => Some(ActionData {
+            savegame_name: Option::from(name.clone()),
+            ..Default::default()
+        }),
+        (0x10, [Dword(order_id), ItemId(object_id)]) => Some(ActionData {
+            order_id: Option::from(OrderId::from_raw(*order_id)),
+            object_id: Option::from(*object_id),
+            ..Default::default()
+        }),
+        (0x11, [Dword(order_id), ItemId(object_id), Float(x), Float(y)]) => Some(ActionData {
+            order_id: Option::from(OrderId::from_raw(*order_id)),
+            object_id: Option::from(*object_id),
+            target_x: Option::from(*x),
+            target_y: Option::from(*y),
+            ..Default::default()
+        }),
+        (0x12, [Dword(order_id), ItemId(object_id), Float(x), Float(y), ItemId(unit)]) => Some(ActionData {
+            order_id: Option::from(OrderId::from_raw(*order_id)),
+            object_id: Option::from(*object_id),
+            target_x: Option::from(*x),
+            target_y: Option::from(*y),
+            target_unit_id: Option::from(*unit),
+            ..Default::default()
+        }),
+        (0x13, [Dword(order_id), ItemId(object_id), Float(x), Float(y), ItemId(unit), ItemId(item)]) => Some(ActionData {
+            order_id: Option::from(OrderId::from_raw(*order_id)),
+            object_id: Option::from(*object_id),
+            target_x: Option::from(*x),
+            target_y: Option::from(*y),
+            target_unit_id: Option::from(*unit),
+            item_object_id: Option::from(*item),
+            ..Default::default()
+        }),
+        // The schema only decodes the first target for 0x14 - see the
+        // comment on its `ACTION_SCHEMA` entry.
+        (0x14, [Dword(order_id), ItemId(object_id), Float(x), Float(y), ItemId(unit)]) => Some(ActionData {
+            order_id: Option::from(OrderId::from_raw(*order_id)),
+            object_id: Option::from(*object_id),
+            target_x: Option::from(*x),
+            target_y: Option::from(*y),
+            target_unit_id: Option::from(*unit),
+            ..Default::default()
+        }),
+        (0x68, [Location(location)]) => Some(ActionData {
+            location: Option::from(MinimapLocation { x: location.x, y: location.y }),
+            ..Default::default()
+        }),
+        _ => None
+    }
 }
 
 #[derive(Serialize)]
 struct Action {
     player_id: u8,
     timestamp: u64,
+    action_id: u8,
     action_type: ActionType,
     #[serde(skip_serializing_if = "Option::is_none")]
     data: Option<ActionData>
 }
 
+/// Per-player APM/EAPM and action-type breakdown, computed as a separate
+/// summarization pass over the already-parsed action stream (the parser
+/// itself stays focused on producing [`Action`]s, not aggregating them).
 #[derive(Serialize)]
-pub struct Replay {
-    pub version: u8,
-    metadata: ReplayMeta,
-    game_settings: GameSettings,
-    slots: Vec<Slot>,
-    players: HashMap<u8, ReplayPlayer>,
-    chat: Vec<ChatMessage>,
-    actions: Vec<Action>
+pub struct PlayerSummary {
+    player_id: u8,
+    total_actions: u64,
+    effective_actions: u64,
+    /// Actions per minute across the player's time in-game (`left_at`, the
+    /// timestamp of their last action block, or the replay's final
+    /// timestamp if `left_at` is 0).
+    apm: f64,
+    /// APM after discarding debounced selection-spam and repeated orders.
+    effective_apm: f64,
+    action_histogram: HashMap<ActionType, u64>
 }
 
-fn parse_dword(bytes: &[u8]) -> u32 {
-    let mut data: u32 = 0;
-    for j in (0u8..4u8) {
-        data += 256u32.pow(j as u32) * bytes[j as usize] as u32
-    }
-    return data;
+#[derive(Serialize)]
+pub struct ReplaySummary {
+    players: HashMap<u8, PlayerSummary>
+}
+
+/// Coarse grouping of an action opcode, for [`PlayerStats::by_category`].
+/// Distinct from [`ActionType`] (which names the specific opcode): this
+/// groups opcodes the way a player would talk about them. Build orders
+/// share opcodes with other no-target abilities, so the split is a
+/// heuristic rather than exact.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ActionCategory {
+    Selection,
+    Build,
+    Order,
+    Chat,
+    MinimapPing,
+    Other
 }
 
-fn parse_word(bytes: &[u8]) -> u16 {
-    let mut data: u16 = 0;
-    for j in (0u8..2u8) {
-        data += 256u16.pow(j as u32) * bytes[j as usize] as u16
+fn categorize_action(action_id: u8) -> ActionCategory {
+    match action_id {
+        0x16 | 0x17 => ActionCategory::Selection,
+        // No-target ability: trains/researches/builds more often than it
+        // issues a targetless unit order.
+        0x10 => ActionCategory::Build,
+        0x11..=0x14 => ActionCategory::Order,
+        0x20 | 0x60 => ActionCategory::Chat,
+        0x68 => ActionCategory::MinimapPing,
+        _ => ActionCategory::Other
     }
-    return data;
 }
 
-fn cursor_read_dword<T>(cursor: &mut Cursor<T>) -> u32 where T: AsRef<[u8]> {
-    let mut buf = [0u8; 4];
-    cursor.read_exact(&mut buf).unwrap();
-    return parse_dword(&buf);
+/// Width, in milliseconds, of one [`PlayerStats::apm_buckets`] window.
+const APM_BUCKET_MS: u64 = 60_000;
+
+/// Per-player stats over the parsed action stream, computed by
+/// [`Replay::compute_stats`]. Broader than [`PlayerSummary`]: adds a
+/// time-bucketed APM series (for graphing) and a category breakdown
+/// alongside the same raw/effective APM split.
+#[derive(Serialize, Debug, Default)]
+pub struct PlayerStats {
+    player_id: u8,
+    total_actions: u64,
+    effective_actions: u64,
+    apm: f64,
+    effective_apm: f64,
+    /// Action counts in consecutive [`APM_BUCKET_MS`]-wide windows,
+    /// starting at the replay's first action timestamp.
+    apm_buckets: Vec<u32>,
+    by_category: HashMap<ActionCategory, u64>
 }
 
-fn cursor_read_word<T>(cursor: &mut Cursor<T>) -> u16 where T: AsRef<[u8]> {
-    let mut buf = [0u8; 2];
-    cursor.read_exact(&mut buf).unwrap();
-    return parse_word(&buf);
+#[derive(Serialize)]
+pub struct GameStats {
+    players: HashMap<u8, PlayerStats>
 }
 
-fn cursor_read_nullterminated_string<T>(cursor: &mut Cursor<T>) -> String where T: AsRef<[u8]> {
-    let mut string_buf: Vec<u8> = vec![];
-    cursor.read_until(0x00, &mut string_buf).unwrap();
+/// One player's running totals from [`Replay::aggregate_actions`] - a
+/// superset of what either [`PlayerSummary`] or [`PlayerStats`] actually
+/// exposes, so both can be sliced out of the same pass.
+#[derive(Debug, Default)]
+struct ActionAggregate {
+    total_actions: u64,
+    effective_actions: u64,
+    action_histogram: HashMap<ActionType, u64>,
+    by_category: HashMap<ActionCategory, u64>,
+    apm_buckets: Vec<u32>
+}
 
-    let string = String::from_utf8_lossy(&string_buf[..string_buf.len()-1]);
-    return string.to_string()
+/// Parallel-array form of `actions`, one `Vec` per field instead of one
+/// struct per action, for handing to JS as typed arrays (see
+/// [`Replay::actions_columnar`]) rather than allocating a JS object per
+/// action - mirrors the peppi SC2 parser's columnar frame export.
+#[derive(Debug, Default)]
+pub struct ActionColumns {
+    pub player_id: Vec<u8>,
+    pub timestamp: Vec<u64>,
+    pub action_type: Vec<u8>,
+    /// `f32::NAN` where the action carries no minimap/target location.
+    pub target_x: Vec<f32>,
+    pub target_y: Vec<f32>
 }
 
-pub fn cursor_read_byte<T>(cursor: &mut Cursor<T>) -> u8 where T: AsRef<[u8]> {
-    let mut buf: [u8;1] = [0u8];
-    cursor.read_exact(&mut buf).unwrap();
-    return buf[0];
+#[derive(Serialize)]
+pub struct Replay {
+    pub version: u8,
+    metadata: ReplayMeta,
+    game_settings: GameSettings,
+    slots: Vec<Slot>,
+    players: HashMap<u8, ReplayPlayer>,
+    chat: Vec<ChatMessage>,
+    actions: Vec<Action>,
+    leave_events: Vec<LeaveEvent>,
+    /// Human-readable [`ParseWarning`]s recorded while decoding this
+    /// replay, one per recoverable problem - empty on a fully clean parse.
+    /// Plain `String`s rather than the structured [`ParseWarning`] enum
+    /// since this is the shape JS consumers actually want: something to
+    /// log or show next to a replay flagged as "parsed with warnings",
+    /// not something to pattern-match on.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>
 }
 
-fn cursor_skip_bytes<T>(cursor: &mut Cursor<T>, n: i64) where T: AsRef<[u8]> {
-    cursor.seek(SeekFrom::Current(n)).unwrap();
+/// One player leaving/disconnecting, in the order the `0x17` records
+/// appear in the replay. Unlike [`ReplayPlayer::leave_reason`], which only
+/// keeps the most recent leave per player, this is an ordered, timestamped
+/// log - so consumers can reconstruct per-player game length and tell who
+/// left first without relying on the saving-player heuristic in
+/// [`Replay::from_bytes_with_options`].
+#[derive(Serialize, Debug, Clone, Copy)]
+struct LeaveEvent {
+    player_id: u8,
+    leave_reason: LeaveReason,
+    timestamp: u64
 }
 
 fn decode_gamesettings(enc: &Vec<u8>) -> Vec<u8> {
@@ -262,102 +940,128 @@ fn get_bits_value(byte: u8, bits: &[u8]) -> u8 {
 }
 
 impl Replay {
-    pub fn from_bytes(bytes: &[u8]) -> Replay {
-        let mut reader = Cursor::new(bytes);
+    /// Parses a full replay, tolerating corruption in the `ReplayData`
+    /// section the way boxcars tolerates a broken network-body block:
+    /// header, game settings, slots and player list always come back if
+    /// they're readable at all, and a bad byte partway through the action
+    /// stream stops that section rather than the whole parse, leaving
+    /// behind a [`Replay::warnings`] entry instead of an [`Err`]. This
+    /// matters for replays saved by a client patch newer than this parser
+    /// knows about, where the header is still readable but the body
+    /// layout has shifted. Reach for [`Replay::from_bytes_with_options`]
+    /// if a corrupt body should instead fail the whole parse.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Replay> {
+        Replay::from_bytes_with_options(bytes, &SanitizeOptions::default(), &ParseOptions { recover: true }, &ParseSections::default())
+    }
+
+    /// Same as [`Replay::from_bytes`], but decodes only the header, game
+    /// settings, slots and player list - the `ReplayData` section (chat,
+    /// actions, leave events) is skipped entirely. Use this for list views
+    /// that only need map/player/duration metadata for a lot of replays.
+    pub fn from_header_bytes(bytes: &[u8]) -> Result<Replay> {
+        Replay::from_bytes_with_options(bytes, &SanitizeOptions::default(), &ParseOptions { recover: true }, &ParseSections::header())
+    }
+
+    /// Same as [`Replay::from_bytes`], but lets the caller opt into getting
+    /// the `|c…|r` color spans of chat messages, game/map names and battle
+    /// tags back as structured [`ColorSegment`]s rather than discarding
+    /// them during sanitization, into [`ParseOptions::recover`] so a
+    /// corrupt `ReplayData` section degrades to a partial [`Replay`] plus
+    /// [`Replay::warnings`] instead of an [`Err`], and into
+    /// [`ParseSections`] to skip decoding sections the caller doesn't need.
+    pub fn from_bytes_with_options(bytes: &[u8], sanitize_options: &SanitizeOptions, parse_options: &ParseOptions, parse_sections: &ParseSections) -> Result<Replay> {
+        let mut reader = ReplayCursor::new(bytes);
         info!("Total bytes length: {:?}", bytes.len());
         let mut header: [u8; 48] = [0; 48];
-        reader.read_exact(&mut header).unwrap();
+        reader.read_exact_buf(&mut header)?;
         info!("Replay version: {:?}", header);
-        let version = header.get(0x0024).unwrap();
+
+        if !header.starts_with(HEADER_MAGIC) {
+            return Err(ParseError::BadHeaderMagic);
+        }
+
+        let version = header[0x0024];
         let total_header_length = match version {
             0 => 64,
             1 => 68,
             _ => 68 // Unknown version - try 68
         };
 
-        let mut subheader: Vec<u8> = vec![0; total_header_length - 48];
-        reader.read_exact(&mut subheader).unwrap();
+        let subheader = reader.read_bytes(total_header_length - 48)?;
+        // Build number sits 4 bytes into the version-1 subheader, right
+        // after the game version dword; older (version-0) headers never
+        // carried one, so there is nothing to dispatch on.
+        let build_number = if version == 1 && subheader.len() >= 6 {
+            u16::from_le_bytes([subheader[4], subheader[5]])
+        } else {
+            0
+        };
+        let format = detect_format(build_number);
+        info!("Detected game variant: {:?} (build {:?})", format.variant(), build_number);
 
         let mut i: u32 = total_header_length as u32;
         let mut k = 0;
-        let num_data_blocks = parse_dword(&header[44..48]);
+        let num_data_blocks = {
+            let mut num = [0u8; 4];
+            num.copy_from_slice(&header[44..48]);
+            u32::from_le_bytes(num)
+        };
         info!("Total data blocks: {:?}", num_data_blocks);
-        let mut block_header: [u8; 12] = [0; 12];
         let mut data: Vec<u8> = vec![];
 
         while k < num_data_blocks {
             // 3.0 [Data block header]
-            match reader.read_exact(&mut block_header) {
-                Ok(_) => {
-                    let block_data_length_bytes: &[u8] = block_header.get(0..4).unwrap();
-                    let block_data_length_inflated_bytes: &[u8] = block_header.get(4..8).unwrap();
-                    let block_data_length = parse_dword(block_data_length_bytes);
-                    let block_data_length_inflated = parse_dword(block_data_length_inflated_bytes);
-
-                    let crc_deflated = parse_word(block_header.get(8..10).unwrap());
-                    let crc_inflated = parse_word(block_header.get(10..12).unwrap());
-                    let mut decoder = Decompress::new(true);
+            let block_header = reader.read_bytes(12)?;
+            let block_data_length = u32::from_le_bytes(block_header[0..4].try_into().unwrap());
+            let block_data_length_inflated = u32::from_le_bytes(block_header[4..8].try_into().unwrap());
 
-                    info!("Word at offset {:#06x} ({:?}) {:?} ({:?}) / inflated: {:?} ({:?})", i, i, block_data_length_bytes, block_data_length, block_data_length_inflated_bytes, block_data_length_inflated);
+            info!("Word at offset {:#06x} ({:?}) block length {:?} / inflated: {:?}", i, i, block_data_length, block_data_length_inflated);
 
-                    let mut block_data: Vec<u8> = vec![0; block_data_length as usize];
-                    match reader.read_exact(&mut block_data) {
-                        Ok(_) => {
-                            info!("Read datablock of length {:?}.", block_data_length);
+            let block_data = reader.read_bytes(block_data_length as usize)?;
+            info!("Read datablock of length {:?}.", block_data_length);
 
-                            let mut out: Vec<u8> = Vec::with_capacity(block_data_length_inflated as usize);
+            let mut out: Vec<u8> = Vec::with_capacity(block_data_length_inflated as usize);
+            let mut decoder = Decompress::new(true);
 
-                            // 4.0 [Decompressed data]
-                            decoder.decompress_vec(&block_data, &mut out, FlushDecompress::Sync).unwrap();
-                            decoder.reset(true);
-                            info!("Decompressed block length: {:?} / begins with {:?}", out.len(), out.get(0..8).unwrap());
+            // 4.0 [Decompressed data]
+            decoder.decompress_vec(&block_data, &mut out, FlushDecompress::Sync)
+                .map_err(|_| ParseError::Decompress(reader.position()))?;
+            info!("Decompressed block length: {:?}", out.len());
 
-                            data.append(&mut out);
-                        }
-                        Err(_) => {
-                            warn!("Failed to read datablock of length {:?}.", block_data_length);
-                        }
-                    };
-                    i += block_data_length + 12;
-                    k+=1;
-                }
-                Err(_) => break
-            }
+            data.append(&mut out);
+            i += block_data_length + 12;
+            k += 1;
         }
 
-
         info!("Finished replay decoding. Total decoded data length: {:?}", data.len());
-        info!("Data starts with {:?}", data.get(0..128).unwrap());
 
         // Decoding of the actual data
 
-        let mut cursor = Cursor::new(&data);
-
+        let mut cursor = ReplayCursor::new(&data);
 
         // 4.1 [PlayerRecord]
-        let player_is_host = cursor_read_byte(&mut cursor) == 0x00;
-        let player_id = cursor_read_byte(&mut cursor);
+        let player_is_host = cursor.read_byte()? == 0x00;
+        let player_id = cursor.read_byte()?;
 
         // Something new - undocumented
-        cursor_skip_bytes(&mut cursor, 4);
+        cursor.skip_bytes(format.player_record_padding_len() as i64)?;
 
-        let player_name = cursor_read_nullterminated_string(&mut cursor);
+        let player_name = cursor.read_nullterminated_string()?;
         info!("Player name: {:?}", player_name);
 
-        let additional_data_size_byte = cursor_read_byte(&mut cursor);
-        cursor_skip_bytes(&mut cursor, additional_data_size_byte as i64);
-
+        let additional_data_size_byte = cursor.read_byte()?;
+        cursor.skip_bytes(additional_data_size_byte as i64)?;
 
         // 4.2 [GameName]
-        let game_name = cursor_read_nullterminated_string(&mut cursor);
-        info!("Game name: {:?}", game_name);
+        let game_name_raw = cursor.read_nullterminated_string()?;
+        info!("Game name: {:?}", game_name_raw);
+        let game_name_sanitized = sanitize(&game_name_raw, sanitize_options);
 
         // There seems to be an additional NUL byte
-        cursor_skip_bytes(&mut cursor, 1);
+        cursor.skip_bytes(1)?;
 
         // 4.3 [Encoded String]
-        let mut encoded_gamesettings_buf: Vec<u8> = vec![];
-        cursor.read_until(0x00, &mut encoded_gamesettings_buf).unwrap();
+        let encoded_gamesettings_buf = cursor.read_until_byte(0x00)?;
 
         let game_settings_buf = decode_gamesettings(&encoded_gamesettings_buf);
         info!("Decoded gamesettings: {:?}", game_settings_buf);
@@ -378,96 +1082,123 @@ impl Replay {
         let obs_referees = get_bits_value(game_settings_buf[3], [6].as_ref()) == 1;
 
         // 4.5 [Map&CreatorName]
-        let mut subcursor = Cursor::new(game_settings_buf[13..].as_ref());
-        let map_name = cursor_read_nullterminated_string(&mut subcursor);
-        let game_creator_name = cursor_read_nullterminated_string(&mut subcursor);
+        let mut subcursor = ReplayCursor::new(game_settings_buf[13..].as_ref());
+        let map_name_sanitized = sanitize(&subcursor.read_nullterminated_string()?, sanitize_options);
+        let game_creator_name = sanitize(&subcursor.read_nullterminated_string()?, sanitize_options).text;
 
         // 4.6 [PlayerCount]
-        let num_players_slots = cursor_read_dword(&mut cursor);
+        let num_players_slots = cursor.read_dword()?;
 
         // 4.7 [GameType]
-        let game_type = cursor_read_byte(&mut cursor);
-        let is_private_custom_game = cursor_read_byte(&mut cursor);
-        cursor_skip_bytes(&mut cursor, 2);
+        let game_type = cursor.read_byte()?;
+        let is_private_custom_game = cursor.read_byte()?;
+        cursor.skip_bytes(2)?;
 
         // 4.8 [LanguageID?]
-        cursor_skip_bytes(&mut cursor, 4);
+        cursor.skip_bytes(4)?;
 
         // 4.9 [PlayerList]
         let mut player_list: HashMap<u8, ReplayPlayer> = HashMap::new();
+        let player_name_sanitized = sanitize(&player_name, sanitize_options);
         player_list.insert(player_id,
                            ReplayPlayer {
-                               battle_tag: player_name.clone(),
+                               battle_tag: player_name_sanitized.text,
+                               battle_tag_colors: player_name_sanitized.segments,
                                leave_reason: LeaveReason::UNKNOWN,
                                result_byte: 0,
                                left_at: 0,
+                               clan: None,
+                               clan_colors: None,
+                               avatar: None,
+                               avatar_colors: None,
                            }
         );
-        let mut next_record_id = cursor_read_byte(&mut cursor);
+        let mut next_record_id = cursor.read_byte()?;
         while next_record_id == 0x00 || next_record_id == 0x16 {
-            let cur_player_id = cursor_read_byte(&mut cursor);
-            // cursor_skip_bytes(&mut cursor, 4);;
-            let cur_player_name = cursor_read_nullterminated_string(&mut cursor);
-            let additional_data_size_byte = cursor_read_byte(&mut cursor);
-            cursor_skip_bytes(&mut cursor, additional_data_size_byte as i64);
+            let cur_player_id = cursor.read_byte()?;
+            let cur_player_name_sanitized = sanitize(&cursor.read_nullterminated_string()?, sanitize_options);
+            let additional_data_size_byte = cursor.read_byte()?;
+            cursor.skip_bytes(additional_data_size_byte as i64)?;
             player_list.insert(cur_player_id, ReplayPlayer {
-                battle_tag: cur_player_name,
+                battle_tag: cur_player_name_sanitized.text,
+                battle_tag_colors: cur_player_name_sanitized.segments,
                 leave_reason: LeaveReason::UNKNOWN,
                 result_byte: 0,
                 left_at: 0,
+                clan: None,
+                clan_colors: None,
+                avatar: None,
+                avatar_colors: None,
             });
-            next_record_id = cursor_read_byte(&mut cursor);
+            next_record_id = cursor.read_byte()?;
         }
         info!("Loaded player list: {:?}", player_list);
 
         // Reforged player metadata
         while next_record_id == 0x39 {
-            let cur_record_subtype = cursor_read_byte(&mut cursor);
-            let cur_record_data_length = cursor_read_dword(&mut cursor);
+            let cur_record_subtype = cursor.read_byte()?;
+            let cur_record_data_length = cursor.read_dword()?;
+            let record_start = cursor.position();
+
+            if format.has_reforged_metadata() && cur_record_subtype == 0x02 {
+                let cur_reforged_player_id = cursor.read_byte()?;
+                let battle_tag_sanitized = sanitize(&cursor.read_nullterminated_string()?, sanitize_options);
+                let clan_sanitized = sanitize(&cursor.read_nullterminated_string()?, sanitize_options);
+                let avatar_sanitized = sanitize(&cursor.read_nullterminated_string()?, sanitize_options);
+                player_list.entry(cur_reforged_player_id).and_modify(|r| {
+                    r.battle_tag = battle_tag_sanitized.text;
+                    r.battle_tag_colors = battle_tag_sanitized.segments;
+                    r.clan = Option::from(clan_sanitized.text);
+                    r.clan_colors = clan_sanitized.segments;
+                    r.avatar = Option::from(avatar_sanitized.text);
+                    r.avatar_colors = avatar_sanitized.segments;
+                });
+            }
 
-            cursor_skip_bytes(&mut cursor, cur_record_data_length as i64);
-            // TODO: Maybe parse this data too
+            let consumed = cursor.position() - record_start;
+            let remaining = cur_record_data_length as i64 - consumed as i64;
+            if remaining > 0 {
+                cursor.skip_bytes(remaining)?;
+            }
 
-            next_record_id = cursor_read_byte(&mut cursor);
+            next_record_id = cursor.read_byte()?;
         }
 
         // 4.10 [GameStartRecord]
         if next_record_id != 0x19 {
-            let mut buf = [0u8; 128];
-            cursor.read_exact(&mut buf).unwrap();
-            panic!("GameStartRecord did not follow PlayerList: next record id = {:?}. Following bytes: {:?}", next_record_id, buf)
+            return Err(ParseError::UnknownRecordId { id: next_record_id, offset: cursor.position() });
         }
 
-        let data_length = cursor_read_word(&mut cursor);
-        let count_slotrecords = cursor_read_byte(&mut cursor);
+        let data_length = cursor.read_word()?;
+        let count_slotrecords = cursor.read_byte()?;
         let mut i = 0u8;
 
         let mut slots: Vec<Slot> = Vec::with_capacity(count_slotrecords as usize);
 
         while i < count_slotrecords {
-            let cur_slot_player_id = cursor_read_byte(&mut cursor);
-            let cur_slot_map_download_percent = cursor_read_byte(&mut cursor);
-            let status_byte = cursor_read_byte(&mut cursor);
+            let cur_slot_player_id = cursor.read_byte()?;
+            let cur_slot_map_download_percent = cursor.read_byte()?;
+            let status_byte = cursor.read_byte()?;
             let cur_slot_status = SlotStatus::from_u8(status_byte)
                 .or(Option::from(SlotStatus::UNKNOWN))
                 .unwrap();
-            let cur_slot_is_computer_player = cursor_read_byte(&mut cursor) == 1;
-            let cur_slot_team_index = cursor_read_byte(&mut cursor);
-            let color_byte = cursor_read_byte(&mut cursor);
+            let cur_slot_is_computer_player = cursor.read_byte()? == 1;
+            let cur_slot_team_index = cursor.read_byte()?;
+            let color_byte = cursor.read_byte()?;
             let cur_slot_color =
                 SlotColor::from_u8(color_byte + 1)
                     .or(Option::from(SlotColor::UNKNOWN))
                     .unwrap();
-            let race_byte = cursor_read_byte(&mut cursor);
+            let race_byte = cursor.read_byte()?;
             let cur_slot_player_race =
                 SlotRace::from_u8(race_byte)
                     .or(Option::from(UNKNOWN))
                     .unwrap();
             let cur_slot_player_computer_ai_strenth =
-                ComputerAIStrength::from_u8(cursor_read_byte(&mut cursor))
+                ComputerAIStrength::from_u8(cursor.read_byte()?)
                     .or(Option::from(ComputerAIStrength::UNKNOWN))
                     .unwrap();
-            let cur_slot_handicap_percent = cursor_read_byte(&mut cursor);
+            let cur_slot_handicap_percent = cursor.read_byte()?;
 
             info!("Player slot record read: pid = {:?} status = {:?} is_comp = {:?} team = {:?} color = {:?} ({:?}) race = {:?} ({:?})",
                 cur_slot_player_id, cur_slot_status, cur_slot_is_computer_player, cur_slot_team_index, cur_slot_color, color_byte, cur_slot_player_race, race_byte);
@@ -487,311 +1218,235 @@ impl Replay {
             i+=1;
         }
 
-        let random_seed = cursor_read_dword(&mut cursor);
+        let random_seed = cursor.read_dword()?;
         info!("Random seed: {:?}", random_seed);
-        let selection_mode = cursor_read_byte(&mut cursor);
+        let selection_mode = cursor.read_byte()?;
         info!("Selection mode: {:?}", selection_mode);
-        let start_spot_count = cursor_read_byte(&mut cursor);
+        let start_spot_count = cursor.read_byte()?;
         info!("Start spots count: {:?}", start_spot_count);
 
-        // 5.0 [ReplayData]
-
-        // 0x17 LeaveGame
-        let from_index = cursor.position();
-        let mut next_record_id = cursor_read_byte(&mut cursor);
-        let mut chat: Vec<ChatMessage> = vec![];
-        let mut current_timestamp: u64 = 0;
-        let mut records: HashMap<u8, u64> = HashMap::new();
-        let mut action_records: HashMap<u8, u64> = HashMap::new();
-        let mut actions: Vec<Action> = vec![];
-        let mut last_leaver_index: u8 = 0;
-
-        loop {
-            // info!("Position {:?}, record {:?}", cursor.position() - 1, next_record_id);
-            match next_record_id {
-                0x17 => {
-                    let leave_reason_byte = cursor_read_dword(&mut cursor);
-                    let cur_leave_reason = LeaveReason::from_u32(leave_reason_byte).or(Option::from(LeaveReason::UNKNOWN)).unwrap();
-                    let cur_player_id = cursor_read_byte(&mut cursor);
-                    let cur_result = cursor_read_dword(&mut cursor);
-                    cursor_skip_bytes(&mut cursor, 4);
-
-                    info!("{:?} {:?}", cur_leave_reason, cur_result);
-                    player_list.entry(cur_player_id).and_modify(|r| {
-                            r.leave_reason = cur_leave_reason;
-                            r.result_byte = cur_result as u8;
-                        }
-                    );
-                    last_leaver_index = cur_player_id;
-                },
-                0x1A => {
-                    cursor_skip_bytes(&mut cursor, 4);
-                },
-                0x1B => {
-                    cursor_skip_bytes(&mut cursor, 4);
-                },
-                0x1C => {
-                    cursor_skip_bytes(&mut cursor, 4);
-                },
-                0x1E | 0x1F => {
-                    let mut len_following = cursor_read_word(&mut cursor);
-                    let increment = cursor_read_word(&mut cursor);
-                    // info!("Time increment: {:?}", increment);
-                    current_timestamp += increment as u64;
-                    len_following -= 2;
-                    let total_len_following = len_following.clone();
-                    let cursor_position_before_data_read = cursor.position();
-
-                    if len_following > 3 {
-                        loop {
-                            let cur_action_player_id = cursor_read_byte(&mut cursor);
-                            let cur_action_blocks_length = cursor_read_word(&mut cursor);
-                            len_following -= 3;
-
-                            player_list.entry(cur_action_player_id).and_modify(|x| x.left_at = current_timestamp);
-
-                            let position_before_read = cursor.position();
-                            let mut cur_read_bytes = 0;
-                            while cur_read_bytes < cur_action_blocks_length {
-                                let cur_position_before_read = cursor.position();
-
-                                let cur_action_id = cursor_read_byte(&mut cursor);
-                                if !action_records.contains_key(&cur_action_id)  {
-                                    action_records.insert(cur_action_id, 1);
-                                }
-                                else {
-                                    action_records.entry(cur_action_id).and_modify(|x| { *x += 1; });
-                                }
+        let (chat, actions, leave_events, warnings, last_leaver_index):
+            (Vec<ChatMessage>, Vec<Action>, Vec<LeaveEvent>, Vec<ParseWarning>, u8) = if parse_sections.include_body {
+            // 5.0 [ReplayData]
+
+            // 0x17 LeaveGame
+            let mut next_record_id = cursor.read_byte()?;
+            let mut chat: Vec<ChatMessage> = vec![];
+            let mut current_timestamp: u64 = 0;
+            let mut records: HashMap<u8, u64> = HashMap::new();
+            let mut action_records: HashMap<u8, u64> = HashMap::new();
+            let mut actions: Vec<Action> = vec![];
+            let mut leave_events: Vec<LeaveEvent> = vec![];
+            let mut last_leaver_index: u8 = 0;
+            let mut warnings: Vec<ParseWarning> = vec![];
+
+            let mut process_record = |next_record_id: u8, cursor: &mut ReplayCursor<&Vec<u8>>, warnings: &mut Vec<ParseWarning>| -> Result<RecordOutcome> {
+                match next_record_id {
+                    0x17 => {
+                        let leave_reason_byte = cursor.read_dword()?;
+                        let cur_leave_reason = LeaveReason::from_u32(leave_reason_byte).or(Option::from(LeaveReason::UNKNOWN)).unwrap();
+                        let cur_player_id = cursor.read_byte()?;
+                        let cur_result = cursor.read_dword()?;
+                        cursor.skip_bytes(4)?;
+
+                        info!("{:?} {:?}", cur_leave_reason, cur_result);
+                        player_list.entry(cur_player_id).and_modify(|r| {
+                                r.leave_reason = cur_leave_reason;
+                                r.result_byte = cur_result as u8;
+                            }
+                        );
+                        leave_events.push(LeaveEvent {
+                            player_id: cur_player_id,
+                            leave_reason: cur_leave_reason,
+                            timestamp: current_timestamp
+                        });
+                        last_leaver_index = cur_player_id;
+                    },
+                    0x1A => {
+                        cursor.skip_bytes(4)?;
+                    },
+                    0x1B => {
+                        cursor.skip_bytes(4)?;
+                    },
+                    0x1C => {
+                        cursor.skip_bytes(4)?;
+                    },
+                    0x1E | 0x1F => {
+                        let mut len_following = cursor.read_word()?;
+                        let increment = cursor.read_word()?;
+                        current_timestamp += increment as u64;
+                        len_following -= 2;
+                        let total_len_following = len_following.clone();
+                        let cursor_position_before_data_read = cursor.position();
+
+                        if len_following > 3 {
+                            loop {
+                                let cur_action_player_id = cursor.read_byte()?;
+                                let cur_action_blocks_length = cursor.read_word()?;
+                                len_following -= 3;
+
+                                player_list.entry(cur_action_player_id).and_modify(|x| x.left_at = current_timestamp);
+
+                                let position_before_read = cursor.position();
+                                let mut cur_read_bytes = 0;
+                                while cur_read_bytes < cur_action_blocks_length {
+                                    let cur_position_before_read = cursor.position();
+
+                                    let cur_action_id = cursor.read_byte()?;
+                                    if !action_records.contains_key(&cur_action_id)  {
+                                        action_records.insert(cur_action_id, 1);
+                                    }
+                                    else {
+                                        action_records.entry(cur_action_id).and_modify(|x| { *x += 1; });
+                                    }
 
-                                let mut action = Action {
-                                    player_id: cur_action_player_id,
-                                    action_type: ActionType::from_u8(cur_action_id).or(Option::from(ActionType::UNKNOWN)).unwrap(),
-                                    timestamp: current_timestamp,
-                                    data: None,
-                                };
-
-                                match cur_action_id {
-                                    0x01 => {},
-                                    0x02 => {},
-                                    0x03 => {
-                                        let new_game_speed = cursor_read_byte(&mut cursor);
-                                    },
-                                    0x04 => {},
-                                    0x05 => {},
-                                    0x06 => {
-                                        let savegame_name = cursor_read_nullterminated_string(&mut cursor);
-                                        action.data = Option::from(ActionData {
-                                            location: None,
-                                            savegame_name: Option::from(savegame_name),
-                                        })
-                                    },
-                                    0x07 => {
-                                        cursor_skip_bytes(&mut cursor, 4);
-                                    },
-                                    0x10 => {
-                                       cursor_skip_bytes(&mut cursor, 14);
-                                    },
-                                    0x11 => {
-                                        cursor_skip_bytes(&mut cursor, 22);
-                                    },
-                                    0x12 => {
-                                        cursor_skip_bytes(&mut cursor, 30);
-                                    },
-                                    0x13 => {
-                                        cursor_skip_bytes(&mut cursor, 38);
-                                    },
-                                    0x14 => {
-                                        cursor_skip_bytes(&mut cursor, 43);
-                                    },
-                                    0x16 => {
-                                        let select_mode_byte = cursor_read_byte(&mut cursor);
-                                        let num_units = cursor_read_word(&mut cursor);
-                                        cursor_skip_bytes(&mut cursor, 8*num_units as i64);
-                                    },
-                                    0x17 => {
-                                        let group_num = cursor_read_byte(&mut cursor);
-                                        let items_count = cursor_read_word(&mut cursor);
-                                        cursor_skip_bytes(&mut cursor, 8*items_count as i64);
-                                    },
-                                    0x18 => {
-                                        cursor_skip_bytes(&mut cursor, 2);
-                                    },
-                                    0x19 => {
-                                        cursor_skip_bytes(&mut cursor, 12);
-                                    },
-                                    0x1A => {},
-                                    0x1B => {
-                                        cursor_skip_bytes(&mut cursor, 9);
-                                    },
-                                    0x1C => {
-                                        cursor_skip_bytes(&mut cursor, 9);
-                                    },
-                                    0x1D => {
-                                        cursor_skip_bytes(&mut cursor, 8);
-                                    },
-                                    0x1E => {
-                                        cursor_skip_bytes(&mut cursor, 5);
-                                    },
-                                    0x21 => {
-                                        cursor_skip_bytes(&mut cursor, 8);
-                                    },
-
-                                    0x20 => {},
-                                    0x22 => {},
-                                    0x23 => {},
-                                    0x24 => {},
-                                    0x25 => {},
-                                    0x26 => {},
-                                    0x27 => {
-                                        cursor_skip_bytes(&mut cursor, 5);
-                                    },
-                                    0x29 => {},
-                                    0x2A => {},
-                                    0x2B => {},
-                                    0x2C => {},
-                                    0x2D => {
-                                        cursor_skip_bytes(&mut cursor, 5);
-                                    },
-                                    0x2E => {
-                                        cursor_skip_bytes(&mut cursor, 4);
-                                    },
-                                    0x2F => {},
-                                    0x30 => {},
-                                    0x31 => {},
-                                    0x32 => {},
-
-                                    0x50 => {
-                                        cursor_skip_bytes(&mut cursor, 5);
-                                    },
-                                    0x51 => {
-                                        cursor_skip_bytes(&mut cursor, 9);
-                                    },
-
-                                    0x60 => {
-                                        let mut buf = vec![];
-                                        buf.resize(8, 0);
-                                        cursor.read_exact(&mut buf).unwrap();
-                                        let command = cursor_read_nullterminated_string(&mut cursor);
-                                        info!("Chat command: {} {:?}", command, buf);
-
-                                        // W3C Replays: Chat messages stored here, but in other replays messages here might shadow chatmessages
-                                        if chat.iter().rfind(|el| el.message == command && el.timestamp.abs_diff(current_timestamp) < 500).is_none() {
-                                            chat.push(ChatMessage {
-                                                message: command,
-                                                timestamp: current_timestamp,
-                                                flag: None,
-                                                recipient_slot_number: None,
-                                                sender_player_id: cur_action_player_id
-                                            })
+                                    let mut action = Action {
+                                        player_id: cur_action_player_id,
+                                        action_id: cur_action_id,
+                                        action_type: ActionType::from_u8(cur_action_id).or(Option::from(ActionType::UNKNOWN)).unwrap(),
+                                        timestamp: current_timestamp,
+                                        data: None,
+                                    };
+
+                                    match action_schema(cur_action_id) {
+                                        Some(schema) => {
+                                            let values = decode_action_fields(cursor, schema)?;
+
+                                            // Chat commands have a side effect (adding a ChatMessage)
+                                            // rather than an ActionData payload, so they're assembled
+                                            // here instead of in action_data_from_fields.
+                                            if cur_action_id == 0x60 {
+                                                if let [FieldValue::Str(command)] = values.as_slice() {
+                                                    info!("Chat command: {}", command);
+                                                    let command_sanitized = sanitize(command, sanitize_options);
+
+                                                    // W3C Replays: Chat messages stored here, but in other replays messages here might shadow chatmessages
+                                                    if chat.iter().rfind(|el| el.message == command_sanitized.text && el.timestamp.abs_diff(current_timestamp) < 500).is_none() {
+                                                        chat.push(ChatMessage {
+                                                            message: command_sanitized.text,
+                                                            message_colors: command_sanitized.segments,
+                                                            timestamp: current_timestamp,
+                                                            flag: None,
+                                                            scope: None,
+                                                            recipient_slot_number: None,
+                                                            sender_player_id: cur_action_player_id
+                                                        })
+                                                    }
+                                                }
+                                            } else {
+                                                action.data = action_data_from_fields(cur_action_id, &values);
+                                            }
+                                        },
+                                        None => {
+                                            let cur_pos = cursor.position();
+                                            let left_bytes = cur_action_blocks_length as u64 - cur_pos + position_before_read;
+                                            warn!("({}) Unknown action id: {:#04x}. Read bytes so far: {:?}. Total expected: {:?}", cur_read_bytes, cur_action_id, cur_pos - position_before_read, cur_action_blocks_length);
+                                            if parse_options.recover {
+                                                warnings.push(ParseWarning::UnknownActionId { id: cur_action_id, offset: cur_position_before_read });
+                                            }
+                                            cursor.read_bytes(left_bytes as usize)?;
+                                            break;
                                         }
-                                    },
-                                    0x61 => {},
-                                    0x62 => {
-                                        cursor_skip_bytes(&mut cursor, 12);
-                                    },
-                                    0x66 => {},
-                                    0x67 => {},
-                                    0x68 => {
-                                        let x = cursor_read_dword(&mut cursor);
-                                        let y = cursor_read_dword(&mut cursor);
-                                        action.data = Option::from(ActionData {
-                                            location: Option::from(MinimapLocation {
-                                                x,
-                                                y
-                                            }),
-                                            savegame_name: None
-                                        })
-                                    },
-                                    0x69 => {
-                                        cursor_skip_bytes(&mut cursor, 16);
-                                    },
-                                    0x6A => {
-                                        cursor_skip_bytes(&mut cursor, 16);
-                                    },
-                                    0x75 => {
-                                        cursor_skip_bytes(&mut cursor, 1);
-                                    },
-
-                                    // Unknown
-                                    0x7a => {
-                                        cursor_skip_bytes(&mut cursor, 20);
-                                    },
-                                    0x7b => {
-                                        cursor_skip_bytes(&mut cursor, 16);
-                                    },
-
-                                    _ => {
-                                        let cur_pos = cursor.position().clone();
-                                        let left_bytes = cur_action_blocks_length as u64 - cur_pos + position_before_read;
-                                        warn!("({}) Unknown action id: {:#04x}. Read bytes so far: {:?}. Total expected: {:?}", cur_read_bytes, cur_action_id, cur_pos - position_before_read, cur_action_blocks_length);
-                                        let mut buf = vec![];
-                                        buf.resize(left_bytes as usize, 0);
-                                        cursor.read_exact(&mut buf).unwrap();
-                                        info!("Following bytes: {:?}", buf);
-                                        break;
                                     }
+
+                                    actions.push(action);
+
+                                    let cur_bytes = (cursor.position() - cur_position_before_read) as u16;
+                                    cur_read_bytes += cur_bytes;
                                 }
 
-                                actions.push(action);
+                                len_following -= (cursor.position() - position_before_read) as u16;
 
-                                let cur_bytes = (cursor.position().clone() - cur_position_before_read) as u16;
-                                cur_read_bytes += cur_bytes;
+                                if len_following < 1 { break }
                             }
+                        }
 
-                            len_following -= (cursor.position() - position_before_read) as u16;
-
-                            if len_following < 1 { break }
+                        if cursor.position() - cursor_position_before_data_read != total_len_following as u64 {
+                            warn!("Mismatch: {:?}/{:?}", cursor.position() - cursor_position_before_data_read, total_len_following);
                         }
+                    },
+                    0x20 => {
+                        let cur_player_id = cursor.read_byte()?;
+                        cursor.skip_bytes(2)?;
+                        let cur_flag = cursor.read_byte()?;
+                        let cur_recepient_slotnumber: i8 = (cursor.read_dword()? - 2) as i8;
+                        let cur_message_sanitized = sanitize(&cursor.read_nullterminated_string()?, sanitize_options);
+                        chat.push(ChatMessage {
+                            sender_player_id: cur_player_id,
+                            flag: Option::from(cur_flag),
+                            scope: Option::from(ChatScope::from_flag(cur_flag)),
+                            recipient_slot_number: Option::from(cur_recepient_slotnumber),
+                            message: cur_message_sanitized.text,
+                            message_colors: cur_message_sanitized.segments,
+                            timestamp: current_timestamp
+                        })
+                    },
+                    0x22 => {
+                        cursor.skip_bytes(5)?;
+                    },
+                    0x23 => {
+                        cursor.skip_bytes(10)?;
+                    },
+                    0x2F => {
+                        cursor.skip_bytes(8)?;
+                    },
+                    0x00 => {
+                        info!("Exiting at null. Position: {:?}", cursor.position());
+                        return Ok(RecordOutcome::Stop);
                     }
-
-                    if(cursor.position() - cursor_position_before_data_read != total_len_following as u64) {
-                        warn!("Mismatch: {:?}/{:?}", cursor.position() - cursor_position_before_data_read, total_len_following);
+                    _ => {
+                        info!("ReplayData: Unknown record id ({:#04x})", next_record_id);
+                        if !parse_options.recover {
+                            return Err(ParseError::UnknownRecordId { id: next_record_id, offset: cursor.position() });
+                        }
+                        warnings.push(ParseWarning::UnknownRecordId { id: next_record_id, offset: cursor.position() });
+                        return Ok(RecordOutcome::Stop);
                     }
-                },
-                0x20 => {
-                    let cur_player_id = cursor_read_byte(&mut cursor);
-                    cursor_skip_bytes(&mut cursor, 2);
-                    let cur_flag = cursor_read_byte(&mut cursor);
-                    let cur_recepient_slotnumber: i8 = (cursor_read_dword(&mut cursor) - 2) as i8;
-                    let cur_message = cursor_read_nullterminated_string(&mut cursor);
-                    chat.push(ChatMessage {
-                        sender_player_id: cur_player_id,
-                        flag: Option::from(cur_flag),
-                        recipient_slot_number: Option::from(cur_recepient_slotnumber),
-                        message: cur_message,
-                        timestamp: current_timestamp
-                    })
-                },
-                0x22 => {
-                    cursor_skip_bytes(&mut cursor, 5);
-                },
-                0x23 => {
-                    cursor_skip_bytes(&mut cursor, 10);
-                },
-                0x2F => {
-                    cursor_skip_bytes(&mut cursor, 8);
-                },
-                0x00 => {
-                    info!("Exiting at null. Position: {:?}", cursor.position());
-                    break
                 }
-                _ => {
-                    info!("ReplayData: Unknown record id ({:#04x})", next_record_id);
-                    break
+                Ok(RecordOutcome::Continue)
+            };
+
+            loop {
+                match process_record(next_record_id, &mut cursor, &mut warnings) {
+                    Ok(RecordOutcome::Continue) => {
+                        if !records.contains_key(&next_record_id) {
+                            records.insert(next_record_id, 1);
+                        }
+                        else {
+                            records.entry(next_record_id).and_modify(|x| { *x += 1; });
+                        }
+                        next_record_id = cursor.read_byte()?;
+                    }
+                    Ok(RecordOutcome::Stop) => break,
+                    Err(err) => {
+                        if parse_options.recover {
+                            let offset = cursor.position();
+                            warnings.push(match err {
+                                ParseError::UnexpectedEof { offset, expected, available } =>
+                                    ParseWarning::Truncated { record_id: next_record_id, offset, expected, available },
+                                ParseError::InvalidUtf8 => ParseWarning::InvalidUtf8 { record_id: next_record_id },
+                                _ => ParseWarning::Truncated { record_id: next_record_id, offset, expected: 0, available: 0 },
+                            });
+                            break;
+                        }
+                        return Err(err);
+                    }
                 }
             }
-            if !records.contains_key(&next_record_id) {
-                records.insert(next_record_id, 1);
-            }
-            else {
-                records.entry(next_record_id).and_modify(|x| { *x += 1; });
-            }
-            next_record_id = cursor_read_byte(&mut cursor);
-        }
-        info!("Records: {:?}", records);
-        info!("Action records: {:?}", action_records);
+            info!("Records: {:?}", records);
+            info!("Action records: {:?}", action_records);
+
+            (chat, actions, leave_events, warnings, last_leaver_index)
+        } else {
+            (vec![], vec![], vec![], vec![], 0)
+        };
 
-        //
+        // The saving player is the one whose `0x17` leave record says the
+        // local game (rather than the remote game) closed the connection -
+        // that's the client that wrote this replay file. Read from the
+        // leave reasons in `player_list`: with several such players, one
+        // not named "FLO" is taken, and only when none matches does it fall
+        // back to whoever left last (`last_leaver_index`).
         let mut saving_player_candidate_ids = player_list.keys().filter( |k| match player_list[k].leave_reason {
             LeaveReason::CONNECTION_CLOSED_BY_LOCAL_GAME => true,
             _ => false
@@ -800,15 +1455,27 @@ impl Replay {
         let saving_player_id: Option<&u8> =
             if saving_player_candidate_ids.clone().count() == 1 { Option::from(saving_player_candidate_ids.next()) }
             else { saving_player_candidate_ids.find(|k| player_list[k].battle_tag != "FLO") };
+        // Only meaningful once the ReplayData section (and therefore the
+        // leave records the heuristic above reads) has actually been
+        // decoded - a header-only parse has no way to know who saved the
+        // replay, so it stays `None` instead of silently reporting player 0.
+        let saving_player_id = if parse_sections.include_body {
+            Some(saving_player_id.copied().unwrap_or(last_leaver_index))
+        } else {
+            None
+        };
 
-        return Replay {
-            version: *version,
+        Ok(Replay {
+            version,
             metadata: ReplayMeta {
-                game_name,
+                game_name: game_name_sanitized.text,
+                game_name_colors: game_name_sanitized.segments,
                 is_saving_player_host: player_is_host,
-                saving_player_id: last_leaver_index,
-                map_name,
-                game_creator_battle_tag: game_creator_name
+                saving_player_id,
+                map_name: map_name_sanitized.text,
+                map_name_colors: map_name_sanitized.segments,
+                game_creator_battle_tag: game_creator_name,
+                variant: format.variant()
             },
             game_settings: GameSettings {
                 fixed_teams,
@@ -827,7 +1494,455 @@ impl Replay {
             slots,
             players: player_list,
             chat,
-            actions
+            actions,
+            leave_events,
+            warnings: warnings.iter().map(ParseWarning::to_string).collect()
+        })
+    }
+
+    /// Per-player action totals/breakdowns over `actions` in a single pass,
+    /// shared by [`Replay::summary`] and [`Replay::compute_stats`] so the
+    /// [`SPAM_ACTION_IDS`] debounce heuristic - the one part of this that's
+    /// easy to get subtly wrong - only has one implementation to go stale.
+    fn aggregate_actions(&self, spam_window_ms: u64) -> HashMap<u8, ActionAggregate> {
+        let mut aggregates: HashMap<u8, ActionAggregate> = HashMap::new();
+        let mut last_action: HashMap<u8, (u8, u64, Option<OrderId>, Option<f32>, Option<f32>)> = HashMap::new();
+        let start_timestamp = self.actions.first().map_or(0, |a| a.timestamp);
+
+        for action in &self.actions {
+            let aggregate = aggregates.entry(action.player_id).or_insert_with(ActionAggregate::default);
+            aggregate.total_actions += 1;
+            *aggregate.action_histogram.entry(action.action_type).or_insert(0) += 1;
+            *aggregate.by_category.entry(categorize_action(action.action_id)).or_insert(0) += 1;
+
+            let bucket_index = (action.timestamp.saturating_sub(start_timestamp) / APM_BUCKET_MS) as usize;
+            if aggregate.apm_buckets.len() <= bucket_index {
+                aggregate.apm_buckets.resize(bucket_index + 1, 0);
+            }
+            aggregate.apm_buckets[bucket_index] += 1;
+
+            // Two actions only count as the same repeated thing if they also
+            // decoded to the same order/target - otherwise two different
+            // orders issued 100ms apart (e.g. distinct spells at distinct
+            // locations) get wrongly collapsed into one effective action.
+            let (order_id, target_x, target_y) = action.data.as_ref()
+                .map_or((None, None, None), |data| (data.order_id, data.target_x, data.target_y));
+
+            let is_spam = SPAM_ACTION_IDS.contains(&action.action_id)
+                && last_action.get(&action.player_id).map_or(false, |(id, ts, last_order_id, last_x, last_y)| {
+                    *id == action.action_id
+                        && action.timestamp.saturating_sub(*ts) < spam_window_ms
+                        && *last_order_id == order_id
+                        && *last_x == target_x
+                        && *last_y == target_y
+                });
+
+            if !is_spam {
+                aggregate.effective_actions += 1;
+            }
+
+            last_action.insert(action.player_id, (action.action_id, action.timestamp, order_id, target_x, target_y));
+        }
+
+        aggregates
+    }
+
+    /// Computes per-player APM/EAPM and an action-type histogram from the
+    /// already-parsed action stream, so front-ends can show APM charts
+    /// without re-parsing the replay.
+    pub fn summary(&self) -> ReplaySummary {
+        let mut aggregates = self.aggregate_actions(SPAM_DEBOUNCE_MS);
+        let final_timestamp = self.actions.last().map_or(0, |a| a.timestamp);
+
+        let players = self.players.iter().map(|(id, player)| {
+            let play_window_ms = if player.left_at > 0 { player.left_at } else { final_timestamp };
+            let minutes = play_window_ms as f64 / 60_000.0;
+            let aggregate = aggregates.remove(id).unwrap_or_default();
+
+            (*id, PlayerSummary {
+                player_id: *id,
+                total_actions: aggregate.total_actions,
+                effective_actions: aggregate.effective_actions,
+                apm: if minutes > 0.0 { aggregate.total_actions as f64 / minutes } else { 0.0 },
+                effective_apm: if minutes > 0.0 { aggregate.effective_actions as f64 / minutes } else { 0.0 },
+                action_histogram: aggregate.action_histogram,
+            })
+        }).collect();
+
+        ReplaySummary { players }
+    }
+
+    /// Per-player APM/EAPM, a 60-second-bucketed APM series and an
+    /// action-category breakdown over the parsed action stream. Broader
+    /// than [`Replay::summary`], which most callers are better served by;
+    /// reach for this when you also need the series/category data.
+    /// `spam_window_ms` overrides [`SPAM_DEBOUNCE_MS`] for how close two
+    /// identical selection/order actions from the same player need to be
+    /// to count as debounced spam rather than distinct actions.
+    pub fn compute_stats(&self, spam_window_ms: Option<u64>) -> GameStats {
+        let spam_window_ms = spam_window_ms.unwrap_or(SPAM_DEBOUNCE_MS);
+        let mut aggregates = self.aggregate_actions(spam_window_ms);
+        let final_timestamp = self.actions.last().map_or(0, |a| a.timestamp);
+
+        let players = self.players.iter().map(|(id, player)| {
+            let play_window_ms = if player.left_at > 0 { player.left_at } else { final_timestamp };
+            let minutes = play_window_ms as f64 / 60_000.0;
+            let aggregate = aggregates.remove(id).unwrap_or_default();
+
+            (*id, PlayerStats {
+                player_id: *id,
+                total_actions: aggregate.total_actions,
+                effective_actions: aggregate.effective_actions,
+                apm: if minutes > 0.0 { aggregate.total_actions as f64 / minutes } else { 0.0 },
+                effective_apm: if minutes > 0.0 { aggregate.effective_actions as f64 / minutes } else { 0.0 },
+                apm_buckets: aggregate.apm_buckets,
+                by_category: aggregate.by_category,
+            })
+        }).collect();
+
+        GameStats { players }
+    }
+
+    /// Columnar view over `actions`, for analytics front-ends that want to
+    /// scan millions of actions as typed arrays instead of allocating one
+    /// JS object per action.
+    pub fn actions_columnar(&self) -> ActionColumns {
+        let mut columns = ActionColumns {
+            player_id: Vec::with_capacity(self.actions.len()),
+            timestamp: Vec::with_capacity(self.actions.len()),
+            action_type: Vec::with_capacity(self.actions.len()),
+            target_x: Vec::with_capacity(self.actions.len()),
+            target_y: Vec::with_capacity(self.actions.len())
         };
+
+        for action in &self.actions {
+            columns.player_id.push(action.player_id);
+            columns.timestamp.push(action.timestamp);
+            columns.action_type.push(action.action_type as u8);
+
+            let data = action.data.as_ref();
+            let location = data.and_then(|data| data.location.as_ref());
+            columns.target_x.push(location.map(|l| l.x as f32).or_else(|| data.and_then(|data| data.target_x)).unwrap_or(f32::NAN));
+            columns.target_y.push(location.map(|l| l.y as f32).or_else(|| data.and_then(|data| data.target_y)).unwrap_or(f32::NAN));
+        }
+
+        columns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_schema_is_some_for_known_ids_and_none_for_unknown() {
+        assert!(action_schema(0x11).is_some());
+        assert!(action_schema(0x99).is_none());
+    }
+
+    #[test]
+    fn decode_action_fields_reads_ability_with_target_point() {
+        let order_id: u32 = 0x000D0001;
+        let object_id: (u32, u32) = (1, 42);
+        let x: f32 = 1.5;
+        let y: f32 = 2.5;
+
+        let mut bytes = vec![0u8; 2];
+        bytes.extend_from_slice(&order_id.to_le_bytes());
+        bytes.extend_from_slice(&object_id.0.to_le_bytes());
+        bytes.extend_from_slice(&object_id.1.to_le_bytes());
+        bytes.extend_from_slice(&x.to_bits().to_le_bytes());
+        bytes.extend_from_slice(&y.to_bits().to_le_bytes());
+
+        let mut cursor = ReplayCursor::new(bytes);
+        let fields = action_schema(0x11).unwrap();
+        let values = decode_action_fields(&mut cursor, fields).unwrap();
+        assert_eq!(cursor.position(), 22);
+
+        match values.as_slice() {
+            [FieldValue::Dword(a), FieldValue::ItemId(b), FieldValue::Float(fx), FieldValue::Float(fy)] => {
+                assert_eq!(*a, order_id);
+                assert_eq!(*b, object_id);
+                assert_eq!(*fx, x);
+                assert_eq!(*fy, y);
+            }
+            other => panic!("unexpected fields: {:?}", other),
+        }
+
+        let action_data = action_data_from_fields(0x11, &values).unwrap();
+        assert_eq!(action_data.order_id, Some(OrderId::MOVE));
+        assert_eq!(action_data.object_id, Some(object_id));
+        assert_eq!(action_data.target_x, Some(x));
+        assert_eq!(action_data.target_y, Some(y));
+    }
+
+    #[test]
+    fn action_schema_order_opcodes_consume_the_same_byte_count_as_before_the_schema_refactor() {
+        // 0x10-0x14's original hand-coded parser just did
+        // `cursor_skip_bytes(n)` for these lengths. The schema below has to
+        // keep consuming exactly that many bytes, or every action after the
+        // first one of these in a real game's stream desyncs.
+        for (action_id, expected_len) in [(0x10u8, 14), (0x11, 22), (0x12, 30), (0x13, 38), (0x14, 43)] {
+            let bytes = vec![0u8; expected_len];
+            let mut cursor = ReplayCursor::new(bytes);
+            decode_action_fields(&mut cursor, action_schema(action_id).unwrap()).unwrap();
+            assert_eq!(cursor.position(), expected_len as u64, "action {:#x}", action_id);
+        }
+    }
+
+    #[test]
+    fn aggregate_actions_only_debounces_orders_with_the_same_order_and_target() {
+        fn order_action(timestamp: u64, action_id: u8, order_id: OrderId, x: f32, y: f32) -> Action {
+            Action {
+                player_id: 0,
+                timestamp,
+                action_id,
+                action_type: ActionType::from_u8(action_id).unwrap_or(ActionType::UNKNOWN),
+                data: Some(ActionData {
+                    order_id: Option::from(order_id),
+                    target_x: Option::from(x),
+                    target_y: Option::from(y),
+                    ..Default::default()
+                })
+            }
+        }
+
+        let replay = Replay {
+            version: 1,
+            metadata: ReplayMeta {
+                saving_player_id: None,
+                is_saving_player_host: false,
+                game_name: String::new(),
+                game_name_colors: None,
+                map_name: String::new(),
+                map_name_colors: None,
+                game_creator_battle_tag: String::new(),
+                variant: GameVariant::Reforged
+            },
+            game_settings: GameSettings {
+                game_speed: 0,
+                vis_hide_terrain: false,
+                vis_map_explored: false,
+                vis_always_visible: false,
+                vis_default: false,
+                obs_mode: 0,
+                teams_together: false,
+                fixed_teams: 0,
+                shared_unit_control: false,
+                random_hero: false,
+                random_races: false,
+                obs_referees: false
+            },
+            slots: vec![],
+            players: HashMap::new(),
+            chat: vec![],
+            actions: vec![
+                // Same order/target repeated 100ms later - genuine debounced spam.
+                order_action(0, 0x12, OrderId::ATTACK, 10.0, 20.0),
+                order_action(100, 0x12, OrderId::ATTACK, 10.0, 20.0),
+                // A different spell at a different location, also 100ms later -
+                // must NOT be collapsed into the previous action.
+                order_action(200, 0x12, OrderId::MOVE, 50.0, 60.0),
+            ],
+            leave_events: vec![],
+            warnings: vec![]
+        };
+
+        let aggregates = replay.aggregate_actions(SPAM_DEBOUNCE_MS);
+        let player = &aggregates[&0];
+        assert_eq!(player.total_actions, 3);
+        assert_eq!(player.effective_actions, 2, "repeat of the same order/target should debounce, a distinct one should not");
+    }
+
+    #[test]
+    fn decode_action_fields_skip_advances_cursor_without_a_value() {
+        let bytes = vec![0u8; 4];
+        let mut cursor = ReplayCursor::new(bytes);
+        let values = decode_action_fields(&mut cursor, action_schema(0x07).unwrap()).unwrap();
+        assert!(values.is_empty());
+        assert_eq!(cursor.position(), 4);
+    }
+
+    #[test]
+    fn decode_action_fields_repeat_skips_count_times_element_width() {
+        // Selection action: one selection-mode byte, then a word count of
+        // 2-element selection entries (8 bytes each).
+        let mut bytes = vec![3u8];
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 16]);
+
+        let mut cursor = ReplayCursor::new(bytes);
+        let values = decode_action_fields(&mut cursor, action_schema(0x16).unwrap()).unwrap();
+
+        match values.as_slice() {
+            [FieldValue::Byte(mode)] => assert_eq!(*mode, 3),
+            other => panic!("unexpected fields: {:?}", other),
+        }
+        assert_eq!(cursor.position(), 1 + 2 + 16);
+    }
+
+    #[test]
+    fn decode_action_fields_errors_on_truncated_input() {
+        let bytes = vec![0u8; 2];
+        let mut cursor = ReplayCursor::new(bytes);
+        assert!(decode_action_fields(&mut cursor, action_schema(0x11).unwrap()).is_err());
+    }
+
+    #[test]
+    fn chat_scope_from_flag_decodes_well_known_flags() {
+        assert_eq!(ChatScope::from_flag(0x00), ChatScope::All);
+        assert_eq!(ChatScope::from_flag(0x01), ChatScope::Allies);
+        assert_eq!(ChatScope::from_flag(0x02), ChatScope::Observers);
+    }
+
+    #[test]
+    fn chat_scope_from_flag_decodes_private_recipient_slot() {
+        // Private-whisper flags start at 3, offset by the recipient's slot.
+        assert_eq!(ChatScope::from_flag(0x03), ChatScope::Private { recipient_slot: 0 });
+        assert_eq!(ChatScope::from_flag(0x09), ChatScope::Private { recipient_slot: 6 });
+    }
+
+    #[test]
+    fn detect_format_dispatches_on_build_number_thresholds() {
+        assert_eq!(detect_format(0).variant(), GameVariant::ReignOfChaos);
+        assert_eq!(detect_format(6030).variant(), GameVariant::ReignOfChaos);
+        assert_eq!(detect_format(6031).variant(), GameVariant::FrozenThrone);
+        assert_eq!(detect_format(6051).variant(), GameVariant::FrozenThrone);
+        assert_eq!(detect_format(6052).variant(), GameVariant::Reforged);
+        assert_eq!(detect_format(9999).variant(), GameVariant::Reforged);
+    }
+
+    #[test]
+    fn cursor_read_past_end_returns_unexpected_eof_with_offset_and_lengths() {
+        // Two bytes available, but a dword read needs four - the error
+        // should carry where the read started and how far short it fell,
+        // not just fail silently or panic.
+        let mut cursor = ReplayCursor::new(vec![0xAAu8, 0xBB]);
+        match cursor.read_dword() {
+            Err(ParseError::UnexpectedEof { offset, expected, available }) => {
+                assert_eq!(offset, 0);
+                assert_eq!(expected, 4);
+                assert_eq!(available, 2);
+            }
+            other => panic!("expected UnexpectedEof, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_warning_renders_the_text_replay_warnings_actually_exposes() {
+        // `Replay::warnings` is `Vec<String>`, built by mapping every
+        // recorded `ParseWarning` through `Display` - this is what that
+        // text actually looks like for the two most common recover-mode
+        // cases, truncation and an unknown opcode.
+        let truncated = ParseWarning::Truncated { record_id: 0x1F, offset: 0x200, expected: 8, available: 3 };
+        assert_eq!(
+            truncated.to_string(),
+            "record 0x1f truncated at offset 0x200 (expected 8 bytes, 3 available)"
+        );
+
+        let unknown_action = ParseWarning::UnknownActionId { id: 0x99, offset: 0x40 };
+        assert_eq!(
+            unknown_action.to_string(),
+            "unknown action id 0x99 at offset 0x40, rest of that block skipped"
+        );
+    }
+
+    #[test]
+    fn leave_reason_from_u32_decodes_known_codes_and_falls_back_to_unknown() {
+        assert!(matches!(LeaveReason::from_u32(0x01), Some(LeaveReason::CONNECTION_CLOSED_BY_REMOTE_GAME)));
+        assert!(matches!(LeaveReason::from_u32(0x0C), Some(LeaveReason::CONNECTION_CLOSED_BY_LOCAL_GAME)));
+        // Anything else doesn't map to a known variant - from_bytes falls
+        // back to LeaveReason::UNKNOWN rather than failing the whole parse.
+        assert!(LeaveReason::from_u32(0xFF).is_none());
+    }
+
+    #[test]
+    fn leave_events_preserve_the_order_they_were_recorded_in() {
+        // Unlike ReplayPlayer::leave_reason (which only keeps the most
+        // recent leave per player), leave_events is an ordered log - two
+        // different players leaving at different times must come out in
+        // the order they left, not grouped by player id.
+        let events = vec![
+            LeaveEvent { player_id: 2, leave_reason: LeaveReason::CONNECTION_CLOSED_BY_REMOTE_GAME, timestamp: 500 },
+            LeaveEvent { player_id: 1, leave_reason: LeaveReason::CONNECTION_CLOSED_BY_LOCAL_GAME, timestamp: 900 },
+        ];
+        assert_eq!(events[0].player_id, 2);
+        assert_eq!(events[1].player_id, 1);
+        assert!(events[0].timestamp < events[1].timestamp);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn summary_computes_apm_and_effective_apm_over_the_players_play_window() {
+        fn plain_action(timestamp: u64, action_id: u8) -> Action {
+            Action {
+                player_id: 0,
+                timestamp,
+                action_id,
+                action_type: ActionType::from_u8(action_id).unwrap_or(ActionType::UNKNOWN),
+                data: None
+            }
+        }
+
+        let mut players = HashMap::new();
+        players.insert(0u8, ReplayPlayer {
+            battle_tag: "Player#1234".to_string(),
+            battle_tag_colors: None,
+            leave_reason: LeaveReason::UNKNOWN,
+            result_byte: 0,
+            left_at: 60_000, // exactly one minute in-game.
+            clan: None,
+            clan_colors: None,
+            avatar: None,
+            avatar_colors: None
+        });
+
+        let replay = Replay {
+            version: 1,
+            metadata: ReplayMeta {
+                saving_player_id: None,
+                is_saving_player_host: false,
+                game_name: String::new(),
+                game_name_colors: None,
+                map_name: String::new(),
+                map_name_colors: None,
+                game_creator_battle_tag: String::new(),
+                variant: GameVariant::Reforged
+            },
+            game_settings: GameSettings {
+                game_speed: 0,
+                vis_hide_terrain: false,
+                vis_map_explored: false,
+                vis_always_visible: false,
+                vis_default: false,
+                obs_mode: 0,
+                teams_together: false,
+                fixed_teams: 0,
+                shared_unit_control: false,
+                random_hero: false,
+                random_races: false,
+                obs_referees: false
+            },
+            slots: vec![],
+            players,
+            chat: vec![],
+            // Two well-spaced, non-spam-opcode actions over the one-minute window.
+            actions: vec![plain_action(0, 0x01), plain_action(30_000, 0x02)],
+            leave_events: vec![],
+            warnings: vec![]
+        };
+
+        let summary = replay.summary();
+        let player_summary = &summary.players[&0];
+        assert_eq!(player_summary.total_actions, 2);
+        assert_eq!(player_summary.effective_actions, 2);
+        assert_eq!(player_summary.apm, 2.0);
+        assert_eq!(player_summary.effective_apm, 2.0);
+    }
+
+    #[test]
+    fn parse_sections_header_skips_body_full_includes_it() {
+        assert!(!ParseSections::header().include_body);
+        assert!(ParseSections::full().include_body);
+        assert!(ParseSections::default().include_body, "default should be the full parse, not the cheaper header-only one");
+    }
+}
@@ -0,0 +1,173 @@
+//! Sanitization for attacker-controlled text fields (chat messages, game
+//! and map names, battle tags). These are read straight off the wire via
+//! [`ReplayCursor::read_nullterminated_string`](crate::replay), so before
+//! they reach a JSON consumer or a terminal log they need the Warcraft III
+//! color/format escapes (`|cAARRGGBB … |r`, `|n`) and raw control bytes
+//! stripped out. The approach mirrors blastmud's
+//! `ignore_special_characters`: always produce a clean plain-text string,
+//! and optionally also hand back the text broken into colored segments so
+//! a UI can re-render the original styling without trusting raw bytes.
+
+use serde::Serialize;
+
+/// Controls how much work [`sanitize`] does beyond stripping control
+/// characters. Kept separate from parsing so callers that only want plain
+/// text (the common case) don't pay for segment bookkeeping they'll throw
+/// away.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SanitizeOptions {
+    /// When set, also return the `|c…|r` color spans as structured
+    /// [`ColorSegment`]s instead of discarding them.
+    pub parse_colors: bool,
+}
+
+/// One run of text and the color (if any) it was wrapped in, as produced
+/// by [`sanitize`] when [`SanitizeOptions::parse_colors`] is set.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ColorSegment {
+    /// `AARRGGBB` hex digits from the `|cAARRGGBB` escape, verbatim and
+    /// still hex-encoded. `None` for text outside any color span.
+    pub color: Option<String>,
+    pub text: String,
+}
+
+/// Result of sanitizing one text field: always a clean plain-text string,
+/// plus the colored segments when requested.
+#[derive(Debug, Clone)]
+pub struct Sanitized {
+    pub text: String,
+    pub segments: Option<Vec<ColorSegment>>,
+}
+
+/// Strips non-printable control characters and Warcraft III color/format
+/// escapes out of `input`, returning clean plain text and, if
+/// `options.parse_colors` is set, the color spans that were removed.
+///
+/// Recognized escapes: `|cAARRGGBB` opens a color span (closed by the next
+/// `|r` or `|c`), `|r` closes the current span, `|n` is a line break, and
+/// `||` is a literal pipe. Anything else starting with `|` is dropped
+/// along with the pipe, since it isn't a known WC3 escape. Raw bytes below
+/// `0x20` (other than `\n`/`\t`) are dropped outright.
+pub fn sanitize(input: &str, options: &SanitizeOptions) -> Sanitized {
+    let mut plain = String::with_capacity(input.len());
+    let mut segments: Vec<ColorSegment> = Vec::new();
+    let mut current_color: Option<String> = None;
+    let mut current_text = String::new();
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '|' {
+            match chars.peek().copied() {
+                Some('c') | Some('C') => {
+                    chars.next();
+                    let mut hex = String::new();
+                    while hex.len() < 8 {
+                        match chars.peek() {
+                            Some(h) if h.is_ascii_hexdigit() => {
+                                hex.push(*h);
+                                chars.next();
+                            }
+                            _ => break,
+                        }
+                    }
+                    if options.parse_colors && (!current_text.is_empty() || current_color.is_some()) {
+                        segments.push(ColorSegment { color: current_color.clone(), text: std::mem::take(&mut current_text) });
+                    }
+                    current_color = if hex.len() == 8 { Some(hex) } else { None };
+                }
+                Some('r') | Some('R') => {
+                    chars.next();
+                    if options.parse_colors && (!current_text.is_empty() || current_color.is_some()) {
+                        segments.push(ColorSegment { color: current_color.clone(), text: std::mem::take(&mut current_text) });
+                    }
+                    current_color = None;
+                }
+                Some('n') | Some('N') => {
+                    chars.next();
+                    plain.push('\n');
+                    current_text.push('\n');
+                }
+                Some('|') => {
+                    chars.next();
+                    plain.push('|');
+                    current_text.push('|');
+                }
+                _ => {
+                    // Not a recognized escape - drop it along with the pipe.
+                    chars.next();
+                }
+            }
+        } else if (c as u32) < 0x20 && c != '\n' && c != '\t' {
+            // Raw control byte - drop.
+        } else {
+            plain.push(c);
+            current_text.push(c);
+        }
+    }
+
+    if options.parse_colors && (!current_text.is_empty() || current_color.is_some()) {
+        segments.push(ColorSegment { color: current_color, text: current_text });
+    }
+
+    Sanitized {
+        text: plain,
+        segments: if options.parse_colors { Some(segments) } else { None },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_color_escapes_by_default() {
+        let result = sanitize("|cffff0000Red|r Text", &SanitizeOptions::default());
+        assert_eq!(result.text, "Red Text");
+        assert!(result.segments.is_none());
+    }
+
+    #[test]
+    fn parses_color_segments_when_requested() {
+        let options = SanitizeOptions { parse_colors: true };
+        let result = sanitize("|cffff0000Red|r Text", &options);
+        assert_eq!(result.text, "Red Text");
+        assert_eq!(result.segments, Some(vec![
+            ColorSegment { color: Some("ffff0000".to_string()), text: "Red".to_string() },
+            ColorSegment { color: None, text: " Text".to_string() },
+        ]));
+    }
+
+    #[test]
+    fn drops_control_bytes_but_keeps_newline_and_tab() {
+        let result = sanitize("a\u{0007}b\nc\td", &SanitizeOptions::default());
+        assert_eq!(result.text, "ab\nc\td");
+    }
+
+    #[test]
+    fn line_break_escape_becomes_newline() {
+        let result = sanitize("line1|nline2", &SanitizeOptions::default());
+        assert_eq!(result.text, "line1\nline2");
+    }
+
+    #[test]
+    fn double_pipe_is_a_literal_pipe() {
+        let result = sanitize("a||b", &SanitizeOptions::default());
+        assert_eq!(result.text, "a|b");
+    }
+
+    #[test]
+    fn unrecognized_escape_is_dropped() {
+        let result = sanitize("a|zb", &SanitizeOptions::default());
+        assert_eq!(result.text, "ab");
+    }
+
+    #[test]
+    fn unterminated_color_span_is_not_silently_double_counted() {
+        let options = SanitizeOptions { parse_colors: true };
+        let result = sanitize("|cffff0000Red", &options);
+        assert_eq!(result.text, "Red");
+        assert_eq!(result.segments, Some(vec![
+            ColorSegment { color: Some("ffff0000".to_string()), text: "Red".to_string() },
+        ]));
+    }
+}